@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+/// Where the config file is read from when --config isn't given:
+/// `$XDG_CONFIG_HOME/panopticon/config.toml`, falling back on `~/.config`, or `%APPDATA%`
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("panopticon").join("config.toml"))
+}
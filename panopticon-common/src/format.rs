@@ -0,0 +1,111 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Appended after the image extension to captures encrypted with age, eg. `.png.age`
+pub const ENCRYPTED_EXTENSION: &str = "age";
+
+/// Appended to a capture while it is being written, eg. `.png.tmp`. The file is renamed into
+/// place once it is complete, so that a capture is never seen half written.
+pub const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// Where a capture is written to before being renamed to `path`
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = OsString::from(path);
+    temporary.push(".");
+    temporary.push(TEMPORARY_EXTENSION);
+    PathBuf::from(temporary)
+}
+
+/// Whether a file is a capture still being written, or left behind by a write that never
+/// finished
+pub fn is_temporary(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(TEMPORARY_EXTENSION))
+}
+
+/// Whether a capture was encrypted, judging by its extension
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(ENCRYPTED_EXTENSION))
+}
+
+/// A capture's path without its image (and encryption) extensions
+pub fn strip_extensions(path: &Path) -> PathBuf {
+    let path = if is_encrypted(path) {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    path.with_extension("")
+}
+
+/// The image formats captures can be stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl CaptureFormat {
+    pub const ALL: [CaptureFormat; 4] = [
+        CaptureFormat::Png,
+        CaptureFormat::Jpeg,
+        CaptureFormat::Webp,
+        CaptureFormat::Avif,
+    ];
+
+    /// The file extension captures in this format are saved with
+    pub fn extension(self) -> &'static str {
+        match self {
+            CaptureFormat::Png => "png",
+            CaptureFormat::Jpeg => "jpg",
+            CaptureFormat::Webp => "webp",
+            CaptureFormat::Avif => "avif",
+        }
+    }
+
+    /// The format of a capture, judging by its extension (before any encryption extension)
+    pub fn from_path(path: &Path) -> Option<CaptureFormat> {
+        let path = if is_encrypted(path) {
+            Path::new(path.file_stem()?)
+        } else {
+            path
+        };
+        let extension = path.extension()?.to_str()?;
+        CaptureFormat::ALL
+            .into_iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl fmt::Display for CaptureFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CaptureFormat::Png => "png",
+            CaptureFormat::Jpeg => "jpeg",
+            CaptureFormat::Webp => "webp",
+            CaptureFormat::Avif => "avif",
+        })
+    }
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(CaptureFormat::Png),
+            "jpeg" | "jpg" => Ok(CaptureFormat::Jpeg),
+            "webp" => Ok(CaptureFormat::Webp),
+            "avif" => Ok(CaptureFormat::Avif),
+            _ => Err(format!(
+                "unknown format {}, expected png, jpeg, webp or avif",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Code shared between the panopticon daemon and panopticon-ics, so that both agree on
+//! how captures are laid out on disk.
+
+pub mod config;
+pub mod format;
+pub mod scan;
+pub mod sidecar;
+pub mod store;
+pub mod template;
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::format::{self, CaptureFormat};
+use crate::template::{ParsedPath, PathTemplate};
+
+/// A capture found on disk
+pub struct Capture {
+    /// Relative to the capture directory
+    pub path: PathBuf,
+    pub parsed: ParsedPath,
+}
+
+// recursively lists every file below dir
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Finds every capture below `dir` that was saved with `template`.
+/// Anything else (eg. sidecars, or captures still being written) is skipped.
+pub fn find_captures(dir: &Path, template: &PathTemplate) -> io::Result<Vec<Capture>> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+
+    let mut captures = Vec::new();
+    for path in files {
+        let Ok(relative_path) = path.strip_prefix(dir) else {
+            continue;
+        };
+        if format::is_temporary(relative_path) || CaptureFormat::from_path(relative_path).is_none()
+        {
+            continue;
+        }
+        let Some(parsed) = template.parse_path(&format::strip_extensions(relative_path)) else {
+            continue;
+        };
+        captures.push(Capture {
+            path: relative_path.to_path_buf(),
+            parsed,
+        });
+    }
+    Ok(captures)
+}
+
+/// Finds the temporary files of captures saved with `template` whose writing never finished,
+/// eg. since the daemon was killed halfway. Other temporary files (eg. of sidecars being
+/// rewritten, or that panopticon didn't write at all) are left out.
+pub fn find_unfinished(dir: &Path, template: &PathTemplate) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.retain(|path| {
+        let Ok(relative_path) = path.strip_prefix(dir) else {
+            return false;
+        };
+        // the capture the temporary file was going to become
+        let finished = relative_path.with_extension("");
+        format::is_temporary(relative_path)
+            && CaptureFormat::from_path(&finished).is_some()
+            && template
+                .parse_path(&format::strip_extensions(&finished))
+                .is_some()
+    });
+    Ok(files)
+}
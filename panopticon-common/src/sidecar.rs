@@ -0,0 +1,332 @@
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The per-day files kept next to the captures, named `YYYY-MM-DD.<suffix>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sidecar {
+    /// The classifications committed in panopticon-ics
+    Labels,
+    /// The failed ticks of the daemon
+    Errors,
+    /// Captures that were skipped since the screen hadn't changed
+    Unchanged,
+    /// What the user was doing during each tick
+    Metadata,
+    /// When the daemon was paused and resumed through `panopticon ctl`
+    Pauses,
+    /// Captures that were suppressed since a blocklisted window had the focus
+    Suppressed,
+    /// When the user went AFK and came back
+    Afk,
+    /// A record of every tick of the daemon
+    Log,
+}
+
+impl Sidecar {
+    pub const ALL: [Sidecar; 8] = [
+        Sidecar::Labels,
+        Sidecar::Errors,
+        Sidecar::Unchanged,
+        Sidecar::Metadata,
+        Sidecar::Pauses,
+        Sidecar::Suppressed,
+        Sidecar::Afk,
+        Sidecar::Log,
+    ];
+
+    /// What the sidecar is called in a database
+    pub fn name(self) -> &'static str {
+        match self {
+            Sidecar::Labels => "labels",
+            Sidecar::Errors => "errors",
+            Sidecar::Unchanged => "unchanged",
+            Sidecar::Metadata => "metadata",
+            Sidecar::Pauses => "pauses",
+            Sidecar::Suppressed => "suppressed",
+            Sidecar::Afk => "afk",
+            Sidecar::Log => "log",
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Sidecar::Labels => "labels.jsonl",
+            Sidecar::Errors => "errors.log",
+            Sidecar::Unchanged => "unchanged.jsonl",
+            Sidecar::Metadata => "metadata.jsonl",
+            Sidecar::Pauses => "pauses.jsonl",
+            Sidecar::Suppressed => "suppressed.jsonl",
+            Sidecar::Afk => "afk.jsonl",
+            Sidecar::Log => "log.jsonl",
+        }
+    }
+
+    /// The file of this kind for `day`
+    pub fn path(self, dir: &Path, day: NaiveDate) -> PathBuf {
+        dir.join(format!("{}.{}", day.format("%Y-%m-%d"), self.suffix()))
+    }
+}
+
+/// Every day that has at least one sidecar in `dir`
+pub fn days(dir: &Path) -> io::Result<BTreeSet<NaiveDate>> {
+    let mut days = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some((day, suffix)) = name.to_str().and_then(|n| n.split_once('.')) else {
+            continue;
+        };
+        if Sidecar::ALL.iter().any(|s| s.suffix() == suffix)
+            && let Ok(day) = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        {
+            days.insert(day);
+        }
+    }
+    Ok(days)
+}
+
+/// A capture that wasn't saved because it looked the same as an earlier one
+#[derive(Serialize, Deserialize)]
+pub struct UnchangedEntry {
+    pub time: DateTime<Local>,
+    pub monitor_id: u32,
+    pub afk: bool,
+    /// The earlier capture, relative to the capture directory
+    pub same_as: PathBuf,
+}
+
+/// The window that had the keyboard focus
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WindowInfo {
+    pub title: String,
+    pub app_name: String,
+    pub pid: u32,
+    /// Where the window sits on the virtual desktop, if known
+    #[serde(default)]
+    pub geometry: Option<Geometry>,
+}
+
+/// Where a monitor sits on the virtual desktop
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MonitorMetadata {
+    pub id: u32,
+    pub name: String,
+    #[serde(flatten)]
+    pub geometry: Geometry,
+}
+
+/// Context recorded alongside the captures of a tick
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TickMetadata {
+    pub time: DateTime<Local>,
+    /// Not recorded when the captures are encrypted
+    pub window: Option<WindowInfo>,
+    /// Not recorded when AFK detection is disabled
+    pub idle_seconds: Option<u64>,
+    /// The monitors that were captured
+    pub monitors: Vec<MonitorMetadata>,
+}
+
+/// What capturing one monitor cost
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureStats {
+    pub monitor_id: u32,
+    /// The size of the saved file, 0 if the screen was unchanged
+    pub bytes: u64,
+    /// Time spent encoding (and encrypting) the capture
+    pub encode_ms: u64,
+    pub unchanged: bool,
+}
+
+/// One line of the capture log, written for every tick whether or not it captured anything
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TickRecord {
+    /// When the tick was due, jitter included
+    pub scheduled: DateTime<Local>,
+    /// When it happened, the time its captures are named after
+    pub time: DateTime<Local>,
+    pub afk: bool,
+    /// The monitors that were captured
+    pub captures: Vec<CaptureStats>,
+    /// How many slots went by without a tick before this one, from `first_missed` on
+    pub missed_slots: i64,
+    /// The first slot that went by, the gap lasting from then until `time`
+    pub first_missed: Option<DateTime<Local>>,
+    pub errors: Vec<String>,
+}
+
+/// How a capture of a blocklisted window was suppressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Suppression {
+    /// No screenshots were taken
+    Skipped,
+    /// The screenshots were taken with the window blacked out
+    BlackedOut,
+}
+
+/// A tick whose captures were suppressed by the blocklist
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SuppressedEntry {
+    pub time: DateTime<Local>,
+    pub suppression: Suppression,
+    /// The blocklist pattern the focused window matched, `None` if it couldn't be told which
+    /// window had the focus or the captures are encrypted
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// The user going AFK (or coming back) at `time`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AfkTransition {
+    pub time: DateTime<Local>,
+    pub afk: bool,
+}
+
+/// A change of the daemon between capturing and being paused
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum PauseEvent {
+    /// `until` is `None` when paused until resumed
+    Pause {
+        time: DateTime<Local>,
+        until: Option<DateTime<Local>>,
+    },
+    Resume {
+        time: DateTime<Local>,
+    },
+}
+
+impl PauseEvent {
+    pub fn time(&self) -> DateTime<Local> {
+        match self {
+            PauseEvent::Pause { time, .. } | PauseEvent::Resume { time } => *time,
+        }
+    }
+}
+
+// whether the last line of `file` is missing its line break, eg. since the disk filled up
+// or the daemon was killed while writing it
+fn ends_unfinished(file: &mut fs::File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0];
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Appends a line of text, without the line break, to the `sidecar` of `day`. An unfinished
+/// line at the end of the sidecar is ended first, rather than glued to this one.
+pub fn append_line(dir: &Path, sidecar: Sidecar, day: NaiveDate, line: &str) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(sidecar.path(dir, day))?;
+    let mut bytes = Vec::with_capacity(line.len() + 2);
+    if ends_unfinished(&mut file)? {
+        bytes.push(b'\n');
+    }
+    bytes.extend_from_slice(line.as_bytes());
+    bytes.push(b'\n');
+    file.write_all(&bytes)
+}
+
+/// Appends one line of JSON to the `sidecar` of `day`
+pub fn append<T: Serialize>(
+    dir: &Path,
+    sidecar: Sidecar,
+    day: NaiveDate,
+    entry: &T,
+) -> io::Result<()> {
+    append_line(dir, sidecar, day, &serde_json::to_string(entry)?)
+}
+
+/// Parses the lines of JSON read from `source`. Blank lines are skipped, and so are lines
+/// that can't be parsed, eg. one cut short when the disk filled up, with a warning.
+pub fn parse_lines<'a, T: for<'de> Deserialize<'de>>(
+    source: &dyn fmt::Display,
+    lines: impl Iterator<Item = &'a [u8]>,
+) -> Vec<T> {
+    let mut entries = Vec::new();
+    for (index, line) in lines.enumerate() {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => eprintln!("skipping line {} of {}: {}", index + 1, source, e),
+        }
+    }
+    entries
+}
+
+/// Reads every line of JSON in the `sidecar` of `day`, see `parse_lines`. A missing sidecar
+/// has no entries.
+pub fn load<T: for<'de> Deserialize<'de>>(
+    dir: &Path,
+    sidecar: Sidecar,
+    day: NaiveDate,
+) -> io::Result<Vec<T>> {
+    let path = sidecar.path(dir, day);
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(parse_lines(
+        &path.display(),
+        contents.split(|b| *b == b'\n'),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn skips_unfinished_lines_and_appends_after_them() {
+        let dir = env::temp_dir().join(format!("panopticon-common-sidecar-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let path = Sidecar::Unchanged.path(&dir, day);
+        let entry = |second| UnchangedEntry {
+            time: "2024-03-01T09:30:00+01:00"
+                .parse::<DateTime<Local>>()
+                .unwrap()
+                + chrono::Duration::seconds(second),
+            monitor_id: 0,
+            afk: false,
+            same_as: PathBuf::from("2024-03-01/09:30:00_screen-0.png"),
+        };
+
+        append(&dir, Sidecar::Unchanged, day, &entry(0)).unwrap();
+        // cut short in the middle of a multibyte character
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"time\":\"2024-03-01T09:30:01+01:00\",\"same_as\":\"\xc3")
+            .unwrap();
+        let entries: Vec<UnchangedEntry> = load(&dir, Sidecar::Unchanged, day).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        append(&dir, Sidecar::Unchanged, day, &entry(2)).unwrap();
+        let entries: Vec<UnchangedEntry> = load(&dir, Sidecar::Unchanged, day).unwrap();
+        let times: Vec<_> = entries.iter().map(|e| e.time).collect();
+        assert_eq!(times, [entry(0).time, entry(2).time]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
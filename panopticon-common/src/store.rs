@@ -0,0 +1,470 @@
+use chrono::NaiveDate;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use crate::format::{self, CaptureFormat};
+use crate::scan::{self, Capture};
+use crate::sidecar::{self, Sidecar};
+use crate::template::PathTemplate;
+
+/// The name of the database in the capture directory when captures are stored in SQLite
+pub const DATABASE_NAME: &str = "panopticon.sqlite3";
+
+/// How long to wait for the other binary to finish writing to the database
+const BUSY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+// captures are keyed by the path they would have as loose files, and every sidecar entry is
+// a row holding its line
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS captures (
+        path TEXT PRIMARY KEY,
+        day TEXT NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS captures_by_day ON captures (day);
+    CREATE TABLE IF NOT EXISTS sidecars (
+        id INTEGER PRIMARY KEY,
+        sidecar TEXT NOT NULL,
+        day TEXT NOT NULL,
+        line TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sidecars_by_day ON sidecars (sidecar, day);
+";
+
+/// How captures and their sidecars are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StoreKind {
+    /// Loose files, a folder per day with the sidecars next to them
+    #[default]
+    Files,
+    /// A single SQLite database
+    Sqlite,
+}
+
+impl fmt::Display for StoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StoreKind::Files => "files",
+            StoreKind::Sqlite => "sqlite",
+        })
+    }
+}
+
+impl FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "files" => Ok(StoreKind::Files),
+            "sqlite" => Ok(StoreKind::Sqlite),
+            _ => Err(format!("unknown store {}, expected files or sqlite", s)),
+        }
+    }
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn day_key(day: NaiveDate) -> String {
+    day.format("%Y-%m-%d").to_string()
+}
+
+// the same on every platform, so that a database can be moved between them
+fn path_key(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A SQLite database holding the captures and sidecars of a capture directory
+#[derive(Clone)]
+pub struct Database {
+    path: PathBuf,
+    // shared by every thread of the daemon that records something
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    fn open(path: &Path) -> io::Result<Database> {
+        let connection = Connection::open(path).map_err(to_io)?;
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(to_io)?;
+        // WAL lets panopticon-ics read while the daemon writes, and incremental vacuuming
+        // gives the space of pruned days back
+        connection
+            .execute_batch("PRAGMA auto_vacuum = INCREMENTAL; PRAGMA journal_mode = WAL;")
+            .map_err(to_io)?;
+        connection.execute_batch(SCHEMA).map_err(to_io)?;
+        Ok(Database {
+            path: path.to_path_buf(),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> io::Result<T> {
+        let mut connection = self.connection.lock().unwrap();
+        f(&mut connection).map_err(to_io)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How many rows each day has, and how many bytes of captures and sidecars they hold
+    pub fn usage(&self) -> io::Result<BTreeMap<NaiveDate, (u64, u64)>> {
+        // the lines are cast to blobs, since the length of text is counted in characters
+        let rows = self.with(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT day, COUNT(*), SUM(LENGTH(data)) FROM captures GROUP BY day
+                 UNION ALL
+                 SELECT day, COUNT(*), SUM(LENGTH(CAST(line AS BLOB))) FROM sidecars GROUP BY day",
+            )?;
+            statement
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, u64>(1)?,
+                        row.get::<_, u64>(2)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+
+        let mut usage: BTreeMap<NaiveDate, (u64, u64)> = BTreeMap::new();
+        for (day, count, bytes) in rows {
+            if let Ok(day) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") {
+                let entry = usage.entry(day).or_default();
+                entry.0 += count;
+                entry.1 += bytes;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Deletes the captures and sidecars of `day`
+    pub fn remove_day(&self, day: NaiveDate) -> io::Result<()> {
+        self.with(|connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute("DELETE FROM captures WHERE day = ?1", [day_key(day)])?;
+            transaction.execute("DELETE FROM sidecars WHERE day = ?1", [day_key(day)])?;
+            transaction.commit()?;
+            connection.execute_batch("PRAGMA incremental_vacuum;")
+        })
+    }
+}
+
+/// Where the captures and sidecars of a capture directory are kept
+#[derive(Clone)]
+pub enum Store {
+    /// Loose files below the directory
+    Files(PathBuf),
+    /// The database in the directory
+    Sqlite(Database),
+}
+
+// writes to a temporary file next to `path` that is renamed into place once complete, so that
+// the viewer never reads a half written capture, even if the daemon is killed meanwhile
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temporary = format::temporary_path(path);
+    let result = fs::File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+// whether any captures or sidecars were saved to `dir` as loose files
+fn has_files(dir: &Path, template: &PathTemplate) -> io::Result<bool> {
+    Ok(!sidecar::days(dir)?.is_empty() || !scan::find_captures(dir, template)?.is_empty())
+}
+
+// the error for finding captures of `other` in `dir`, that `kind` would leave out
+fn mixed_stores(dir: &Path, other: &str, kind: &str) -> io::Error {
+    io::Error::other(format!(
+        "{} already holds {}, which {} would leave out; move that elsewhere first",
+        dir.display(),
+        other,
+        kind
+    ))
+}
+
+impl Store {
+    /// Opens the store of `kind` in `dir`, creating whatever doesn't exist yet. Fails if `dir`
+    /// already holds captures of the other kind, which would no longer be seen.
+    pub fn open(dir: &Path, kind: StoreKind, template: &PathTemplate) -> io::Result<Store> {
+        fs::create_dir_all(dir)?;
+        let database = dir.join(DATABASE_NAME);
+        match kind {
+            StoreKind::Files if database.exists() => {
+                Err(mixed_stores(dir, DATABASE_NAME, "the files store"))
+            }
+            StoreKind::Files => Ok(Store::Files(dir.to_path_buf())),
+            StoreKind::Sqlite if has_files(dir, template)? => Err(mixed_stores(
+                dir,
+                "loose captures or sidecars",
+                "the sqlite store",
+            )),
+            StoreKind::Sqlite => Ok(Store::Sqlite(Database::open(&database)?)),
+        }
+    }
+
+    /// Opens the store the captures in `dir` were saved to: the database if there is one,
+    /// loose files otherwise. Fails if there are both, rather than leave out either.
+    pub fn detect(dir: &Path, template: &PathTemplate) -> io::Result<Store> {
+        let database = dir.join(DATABASE_NAME);
+        if !database.exists() {
+            Ok(Store::Files(dir.to_path_buf()))
+        } else if has_files(dir, template)? {
+            Err(mixed_stores(
+                dir,
+                "loose captures or sidecars",
+                &format!("reading from {}", DATABASE_NAME),
+            ))
+        } else {
+            Ok(Store::Sqlite(Database::open(&database)?))
+        }
+    }
+
+    pub fn kind(&self) -> StoreKind {
+        match self {
+            Store::Files(_) => StoreKind::Files,
+            Store::Sqlite(_) => StoreKind::Sqlite,
+        }
+    }
+
+    /// Where the `sidecar` of `day` is kept, to tell the user
+    pub fn location(&self, sidecar: Sidecar, day: NaiveDate) -> PathBuf {
+        match self {
+            Store::Files(dir) => sidecar.path(dir, day),
+            Store::Sqlite(database) => database.path.clone(),
+        }
+    }
+
+    /// Where a capture is kept, to tell the user
+    pub fn capture_location(&self, relative_path: &Path) -> PathBuf {
+        match self {
+            Store::Files(dir) => dir.join(relative_path),
+            Store::Sqlite(database) => database.path.clone(),
+        }
+    }
+
+    /// Appends a line of text, without the line break, to the `sidecar` of `day`
+    pub fn append_line(&self, sidecar: Sidecar, day: NaiveDate, line: &str) -> io::Result<()> {
+        match self {
+            Store::Files(dir) => sidecar::append_line(dir, sidecar, day, line),
+            Store::Sqlite(database) => database.with(|connection| {
+                connection
+                    .execute(
+                        "INSERT INTO sidecars (sidecar, day, line) VALUES (?1, ?2, ?3)",
+                        params![sidecar.name(), day_key(day), line],
+                    )
+                    .map(|_| ())
+            }),
+        }
+    }
+
+    /// Appends one line of JSON to the `sidecar` of `day`
+    pub fn append<T: Serialize>(
+        &self,
+        sidecar: Sidecar,
+        day: NaiveDate,
+        entry: &T,
+    ) -> io::Result<()> {
+        match self {
+            Store::Files(dir) => sidecar::append(dir, sidecar, day, entry),
+            Store::Sqlite(_) => self.append_line(sidecar, day, &serde_json::to_string(entry)?),
+        }
+    }
+
+    /// Reads every line of JSON in the `sidecar` of `day`, see `sidecar::parse_lines`. A missing
+    /// sidecar has no entries.
+    pub fn load<T: for<'de> Deserialize<'de>>(
+        &self,
+        sidecar: Sidecar,
+        day: NaiveDate,
+    ) -> io::Result<Vec<T>> {
+        let database = match self {
+            Store::Files(dir) => return sidecar::load(dir, sidecar, day),
+            Store::Sqlite(database) => database,
+        };
+        let lines = database.with(|connection| {
+            let mut statement = connection
+                .prepare("SELECT line FROM sidecars WHERE sidecar = ?1 AND day = ?2 ORDER BY id")?;
+            statement
+                .query_map(params![sidecar.name(), day_key(day)], |row| {
+                    row.get::<_, String>(0)
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        let source = format!(
+            "the {} of {} in {}",
+            sidecar.name(),
+            day,
+            database.path.display()
+        );
+        Ok(sidecar::parse_lines(
+            &source,
+            lines.iter().map(|line| line.as_bytes()),
+        ))
+    }
+
+    /// Replaces the `sidecar` of `day` with `entries`, all at once so that a crash never leaves
+    /// it half written
+    pub fn replace<T: Serialize>(
+        &self,
+        sidecar: Sidecar,
+        day: NaiveDate,
+        entries: &[T],
+    ) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for entry in entries {
+            lines.push(serde_json::to_string(entry)?);
+        }
+        match self {
+            Store::Files(dir) => {
+                let mut bytes = Vec::new();
+                for line in &lines {
+                    bytes.extend_from_slice(line.as_bytes());
+                    bytes.push(b'\n');
+                }
+                write_atomically(&sidecar.path(dir, day), &bytes)
+            }
+            Store::Sqlite(database) => database.with(|connection| {
+                let transaction = connection.transaction()?;
+                transaction.execute(
+                    "DELETE FROM sidecars WHERE sidecar = ?1 AND day = ?2",
+                    params![sidecar.name(), day_key(day)],
+                )?;
+                for line in &lines {
+                    transaction.execute(
+                        "INSERT INTO sidecars (sidecar, day, line) VALUES (?1, ?2, ?3)",
+                        params![sidecar.name(), day_key(day), line],
+                    )?;
+                }
+                transaction.commit()
+            }),
+        }
+    }
+
+    /// Whether the `sidecar` of `day` has any entries. One that was emptied by `replace` has
+    /// none, like one that was never written.
+    pub fn contains(&self, sidecar: Sidecar, day: NaiveDate) -> io::Result<bool> {
+        match self {
+            Store::Files(dir) => match fs::read_to_string(sidecar.path(dir, day)) {
+                Ok(contents) => Ok(contents.lines().any(|line| !line.trim().is_empty())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            },
+            Store::Sqlite(database) => database.with(|connection| {
+                connection.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM sidecars WHERE sidecar = ?1 AND day = ?2)",
+                    params![sidecar.name(), day_key(day)],
+                    |row| row.get(0),
+                )
+            }),
+        }
+    }
+
+    /// Every day that has at least one sidecar
+    pub fn days(&self) -> io::Result<BTreeSet<NaiveDate>> {
+        let database = match self {
+            Store::Files(dir) => return sidecar::days(dir),
+            Store::Sqlite(database) => database,
+        };
+        let days = database.with(|connection| {
+            let mut statement = connection.prepare("SELECT DISTINCT day FROM sidecars")?;
+            statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        Ok(days
+            .iter()
+            .filter_map(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok())
+            .collect())
+    }
+
+    /// Finds every capture that was saved with `template`, see `scan::find_captures`
+    pub fn find_captures(&self, template: &PathTemplate) -> io::Result<Vec<Capture>> {
+        let database = match self {
+            Store::Files(dir) => return scan::find_captures(dir, template),
+            Store::Sqlite(database) => database,
+        };
+        let paths = database.with(|connection| {
+            let mut statement = connection.prepare("SELECT path FROM captures")?;
+            statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        Ok(paths
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|path| CaptureFormat::from_path(path).is_some())
+            .filter_map(|path| {
+                let parsed = template.parse_path(&format::strip_extensions(&path))?;
+                Some(Capture { path, parsed })
+            })
+            .collect())
+    }
+
+    /// Saves a capture of `day` under `relative_path`, replacing any capture saved there before
+    pub fn save_capture(
+        &self,
+        relative_path: &Path,
+        day: NaiveDate,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        match self {
+            Store::Files(dir) => {
+                let path = dir.join(relative_path);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                write_atomically(&path, bytes)
+            }
+            Store::Sqlite(database) => database.with(|connection| {
+                connection
+                    .execute(
+                        "INSERT OR REPLACE INTO captures (path, day, data) VALUES (?1, ?2, ?3)",
+                        params![path_key(relative_path), day_key(day), bytes],
+                    )
+                    .map(|_| ())
+            }),
+        }
+    }
+
+    /// Reads the capture saved under `relative_path`
+    pub fn read_capture(&self, relative_path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            Store::Files(dir) => fs::read(dir.join(relative_path)),
+            Store::Sqlite(database) => database
+                .with(|connection| {
+                    connection
+                        .query_row(
+                            "SELECT data FROM captures WHERE path = ?1",
+                            [path_key(relative_path)],
+                            |row| row.get(0),
+                        )
+                        .optional()
+                })?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use chrono::{
+    DateTime, Days, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone,
+};
+use regex::Regex;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The default layout, whose timestamps carry milliseconds and the UTC offset so that they
+/// stay unique when the clocks go back. Windows doesn't allow colons in file names.
+#[cfg(not(windows))]
+pub const DEFAULT_TEMPLATE: &str = "%Y-%m-%d/%H:%M:%S%.3f%z_screen-{monitor_id}{afk}";
+#[cfg(windows)]
+pub const DEFAULT_TEMPLATE: &str = "%Y-%m-%d/%H-%M-%S%.3f%z_screen-{monitor_id}{afk}";
+
+// strftime specifiers that can be both written and read back, with the text they match
+const TIME_SPECIFIERS: &[(char, &str)] = &[
+    ('Y', r"\d{4}"),
+    ('y', r"\d{2}"),
+    ('m', r"\d{2}"),
+    ('d', r"\d{2}"),
+    ('j', r"\d{3}"),
+    ('H', r"\d{2}"),
+    ('M', r"\d{2}"),
+    ('S', r"\d{2}"),
+    ('F', r"\d{4}-\d{2}-\d{2}"),
+    ('T', r"\d{2}:\d{2}:\d{2}"),
+    ('z', r"[+-]\d{4}"),
+];
+
+// fractions of a second, written as %.3f, %.6f, %.9f or %.f (as many digits as needed)
+const FRACTION_DIGITS: &[char] = &['3', '6', '9'];
+
+// the regex matching what a time specifier writes. The fraction of a second and the UTC
+// offset are optional, so that paths written before the default template had them are
+// still found.
+fn time_pattern(spec: &str) -> String {
+    match spec {
+        "%.f" => r"(\.\d+)?".to_string(),
+        "%z" => r"([+-]\d{4})?".to_string(),
+        _ if spec.starts_with("%.") => format!(r"(\.\d{{{}}})?", &spec[2..3]),
+        _ => {
+            let spec = spec.chars().nth(1).unwrap();
+            let (_, re) = TIME_SPECIFIERS.iter().find(|(s, _)| *s == spec).unwrap();
+            format!("({})", re)
+        }
+    }
+}
+
+// characters that can't appear in a placeholder value since they aren't portable in file names
+const RESERVED_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(Debug)]
+pub struct TemplateError(String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid path template: {}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Time(String),
+    MonitorId,
+    MonitorName,
+    Afk,
+    Hostname,
+}
+
+/// The values substituted for the placeholders of a template
+pub struct Fields<'a> {
+    pub monitor_id: u32,
+    pub monitor_name: &'a str,
+    pub afk: bool,
+    pub hostname: &'a str,
+}
+
+/// Everything that could be read back from the path of a capture
+#[derive(Debug, PartialEq)]
+pub struct ParsedPath {
+    /// The local time of the capture, as written in the path
+    pub time: NaiveDateTime,
+    /// The UTC offset that `time` was in, if the path has one
+    pub offset: Option<FixedOffset>,
+    pub monitor_id: u32,
+    pub monitor_name: Option<String>,
+    pub afk: bool,
+    pub hostname: Option<String>,
+}
+
+/// A strftime-style template for the path of a capture relative to the capture directory,
+/// without the file extension. `/` separates directories on every platform.
+///
+/// Besides the strftime specifiers, the following placeholders are supported:
+/// `{monitor_id}`, `{monitor_name}`, `{afk}` (`_AFK` when the user was away) and `{hostname}`.
+#[derive(Clone, Debug)]
+pub struct PathTemplate {
+    source: String,
+    tokens: Vec<Token>,
+    pattern: Regex,
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '%' => match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some(spec) if TIME_SPECIFIERS.iter().any(|(s, _)| *s == spec) => {
+                    Token::Time(format!("%{}", spec))
+                }
+                Some('.') => match (chars.next(), chars.clone().next()) {
+                    (Some('f'), _) => Token::Time("%.f".to_string()),
+                    (Some(digits), Some('f')) if FRACTION_DIGITS.contains(&digits) => {
+                        chars.next();
+                        Token::Time(format!("%.{}f", digits))
+                    }
+                    _ => Err(TemplateError(
+                        "expected %.3f, %.6f, %.9f or %.f for fractions of a second".to_string(),
+                    ))?,
+                },
+                Some(spec) => Err(TemplateError(format!("unsupported specifier %{}", spec)))?,
+                None => Err(TemplateError("trailing %".to_string()))?,
+            },
+            '{' => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                match name.as_str() {
+                    "monitor_id" => Token::MonitorId,
+                    "monitor_name" => Token::MonitorName,
+                    "afk" => Token::Afk,
+                    "hostname" => Token::Hostname,
+                    _ => Err(TemplateError(format!("unknown placeholder {{{}}}", name)))?,
+                }
+            }
+            c => {
+                literal.push(c);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+        tokens.push(token);
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if RESERVED_CHARS.contains(&c) || c.is_control() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+impl PathTemplate {
+    pub fn new(template: &str) -> Result<PathTemplate, TemplateError> {
+        let tokens = tokenize(template)?;
+
+        if !tokens.contains(&Token::MonitorId) {
+            Err(TemplateError(
+                "{monitor_id} is required to tell monitors apart".to_string(),
+            ))?;
+        }
+
+        let mut pattern = String::from("^");
+        for token in &tokens {
+            match token {
+                Token::Literal(s) => pattern.push_str(&regex::escape(s)),
+                Token::Time(spec) => pattern.push_str(&time_pattern(spec)),
+                Token::MonitorId => pattern.push_str(r"(\d+)"),
+                Token::MonitorName | Token::Hostname => pattern.push_str("([^/]+?)"),
+                Token::Afk => pattern.push_str("(_AFK)?"),
+            }
+        }
+        pattern.push('$');
+
+        let template = PathTemplate {
+            source: template.to_string(),
+            tokens,
+            pattern: Regex::new(&pattern).map_err(|e| TemplateError(e.to_string()))?,
+        };
+
+        // make sure that whatever we write can be read back unambiguously
+        let has_afk = template.tokens.contains(&Token::Afk);
+        let sample_time = NaiveDate::from_ymd_opt(2001, 2, 3)
+            .and_then(|d| d.and_hms_opt(4, 5, 6))
+            .unwrap();
+        for afk in [false, true] {
+            let fields = Fields {
+                monitor_id: 7,
+                monitor_name: "monitor",
+                afk,
+                hostname: "host",
+            };
+            let rendered = template.render(&chrono::Utc.from_utc_datetime(&sample_time), &fields);
+            match template.parse_path(&rendered) {
+                Some(parsed) if parsed.time == sample_time && parsed.afk == (afk && has_afk) => {}
+                _ => Err(TemplateError(format!(
+                    "{} doesn't identify the capture time unambiguously",
+                    template.source
+                )))?,
+            }
+        }
+
+        Ok(template)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// How many digits of the fraction of a second the paths hold, so that the times of
+    /// captures can be rounded to what can be read back from their paths
+    pub fn subsec_digits(&self) -> u16 {
+        self.tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Time(spec) if spec == "%.f" => Some(9),
+                Token::Time(spec) if spec.starts_with("%.") => spec[2..3].parse().ok(),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The path of a capture taken at `time`, relative to the capture directory
+    pub fn render<Tz: TimeZone>(&self, time: &DateTime<Tz>, fields: &Fields) -> PathBuf
+    where
+        Tz::Offset: fmt::Display,
+    {
+        let mut rendered = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Literal(s) => rendered.push_str(s),
+                Token::Time(spec) => rendered.push_str(&time.format(spec).to_string()),
+                Token::MonitorId => rendered.push_str(&fields.monitor_id.to_string()),
+                Token::MonitorName => rendered.push_str(&sanitize(fields.monitor_name)),
+                Token::Afk => rendered.push_str(if fields.afk { "_AFK" } else { "" }),
+                Token::Hostname => rendered.push_str(&sanitize(fields.hostname)),
+            }
+        }
+        rendered.split('/').collect()
+    }
+
+    /// Reads back the fields of a path produced by `render`, or `None` if the path doesn't
+    /// follow this template (eg. it is some other file in the capture directory)
+    pub fn parse_path(&self, relative_path: &Path) -> Option<ParsedPath> {
+        let relative_path = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()?
+            .join("/");
+        let captures = self.pattern.captures(&relative_path)?;
+
+        let mut time_text = Vec::new();
+        let mut time_format = Vec::new();
+        let mut parsed = ParsedPath {
+            time: NaiveDateTime::default(),
+            offset: None,
+            monitor_id: 0,
+            monitor_name: None,
+            afk: false,
+            hostname: None,
+        };
+
+        let fields = self
+            .tokens
+            .iter()
+            .filter(|t| !matches!(t, Token::Literal(_)));
+        for (token, capture) in fields.zip(captures.iter().skip(1)) {
+            match token {
+                Token::Literal(_) => {}
+                // the optional fraction of a second and UTC offset may be missing
+                Token::Time(spec) => {
+                    if let Some(capture) = capture {
+                        time_text.push(capture.as_str());
+                        time_format.push(spec.as_str());
+                    } else if !(spec.starts_with("%.") || spec == "%z") {
+                        return None;
+                    }
+                }
+                Token::MonitorId => parsed.monitor_id = capture?.as_str().parse().ok()?,
+                Token::MonitorName => parsed.monitor_name = Some(capture?.as_str().to_string()),
+                Token::Afk => parsed.afk = capture.is_some(),
+                Token::Hostname => parsed.hostname = Some(capture?.as_str().to_string()),
+            }
+        }
+
+        let (time_text, time_format) = (time_text.join("|"), time_format.join("|"));
+        if time_format.contains("%z") {
+            let time = DateTime::parse_from_str(&time_text, &time_format).ok()?;
+            parsed.time = time.naive_local();
+            parsed.offset = Some(*time.offset());
+        } else {
+            parsed.time = NaiveDateTime::parse_from_str(&time_text, &time_format).ok()?;
+        }
+        Some(parsed)
+    }
+}
+
+impl ParsedPath {
+    /// The instant the capture was taken. A path without a UTC offset is read in the local
+    /// time zone: of a time that occurred twice as the clocks went back, the first is taken,
+    /// and a time skipped as the clocks went forward is read with the offset of the day before.
+    pub fn local_time(&self) -> DateTime<Local> {
+        let offset = self.offset.unwrap_or_else(|| {
+            let resolved = match Local.from_local_datetime(&self.time) {
+                LocalResult::Single(time) => Some(time),
+                // not necessarily in order
+                LocalResult::Ambiguous(a, b) => Some(a.min(b)),
+                LocalResult::None => self
+                    .time
+                    .checked_sub_days(Days::new(1))
+                    .and_then(|day_before| Local.from_local_datetime(&day_before).earliest()),
+            };
+            match resolved {
+                Some(time) => time.offset().fix(),
+                None => Local.offset_from_utc_datetime(&self.time).fix(),
+            }
+        });
+        offset
+            .from_local_datetime(&self.time)
+            .single()
+            .map(|time| time.with_timezone(&Local))
+            .unwrap_or_else(|| Local.from_utc_datetime(&self.time))
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PathTemplate::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(afk: bool) -> Fields<'static> {
+        Fields {
+            monitor_id: 3,
+            monitor_name: "DP-1",
+            afk,
+            hostname: "desk",
+        }
+    }
+
+    fn time(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f %z").unwrap()
+    }
+
+    #[test]
+    fn default_template_round_trips() {
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let captured = time("2024-03-01 09:30:15.250 +0100");
+        for afk in [false, true] {
+            let path = template.render(&captured, &fields(afk));
+            let parsed = template.parse_path(&path).unwrap();
+            assert_eq!(parsed.time, captured.naive_local());
+            assert_eq!(parsed.offset, Some(*captured.offset()));
+            assert_eq!(parsed.monitor_id, 3);
+            assert_eq!(parsed.afk, afk);
+        }
+        assert_eq!(template.subsec_digits(), 3);
+    }
+
+    #[test]
+    fn placeholders_round_trip() {
+        let template =
+            PathTemplate::new("{hostname}/%F/%H%M%S_{monitor_name}_{monitor_id}{afk}").unwrap();
+        let captured = time("2024-03-01 09:30:15 +0000");
+        let path = template.render(&captured, &fields(true));
+        assert_eq!(path, Path::new("desk/2024-03-01/093015_DP-1_3_AFK"));
+        let parsed = template.parse_path(&path).unwrap();
+        assert_eq!(parsed.time, captured.naive_local());
+        assert_eq!(parsed.offset, None);
+        assert_eq!(parsed.monitor_name.as_deref(), Some("DP-1"));
+        assert_eq!(parsed.hostname.as_deref(), Some("desk"));
+        assert!(parsed.afk);
+        assert_eq!(template.subsec_digits(), 0);
+    }
+
+    #[test]
+    fn reserved_characters_are_replaced() {
+        let template = PathTemplate::new("%F/%T_{monitor_name}_{monitor_id}").unwrap();
+        let fields = Fields {
+            monitor_name: "a/b:c",
+            ..fields(false)
+        };
+        let path = template.render(&time("2024-03-01 09:30:15 +0000"), &fields);
+        assert_eq!(path, Path::new("2024-03-01/09:30:15_a-b-c_3"));
+    }
+
+    #[test]
+    fn reads_paths_without_fraction_or_offset() {
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let separator = if cfg!(windows) { "-" } else { ":" };
+        let path = PathBuf::from(format!("2024-03-01/09{0}30{0}15_screen-0", separator));
+        let parsed = template.parse_path(&path).unwrap();
+        assert_eq!(parsed.time, time("2024-03-01 09:30:15 +0000").naive_utc());
+        assert_eq!(parsed.offset, None);
+        assert!(!parsed.afk);
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        assert!(
+            template
+                .parse_path(Path::new("2024-03-01.labels"))
+                .is_none()
+        );
+        assert!(template.parse_path(Path::new("notes/todo")).is_none());
+    }
+
+    #[test]
+    fn rejects_ambiguous_templates() {
+        assert!(PathTemplate::new("%F/%T").is_err());
+        assert!(PathTemplate::new("%H%M%S_{monitor_id}").is_err());
+        assert!(PathTemplate::new("%F/%T_{monitor}").is_err());
+        assert!(PathTemplate::new("%F/%T%q_{monitor_id}").is_err());
+        assert!(PathTemplate::new("%F/%T_{monitor_id}%").is_err());
+    }
+}
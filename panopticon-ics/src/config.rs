@@ -0,0 +1,99 @@
+use panopticon_common::config::default_path;
+use panopticon_common::store::StoreKind;
+use panopticon_common::template::PathTemplate;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The settings of the daemon's config file that decide how its captures are saved, so that
+/// they're found without being repeated on the command line
+#[derive(Default)]
+pub struct CaptureSettings {
+    /// The config file they were read from, if any
+    pub path: Option<PathBuf>,
+    pub template: Option<PathTemplate>,
+    pub store: Option<StoreKind>,
+}
+
+// the daemon checks the rest of the file, so unknown keys are left to it
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+    template: Option<String>,
+    #[serde(default)]
+    storage: StorageConfig,
+}
+
+#[derive(Deserialize, Default)]
+struct StorageConfig {
+    store: Option<String>,
+}
+
+/// Reads the daemon's config file at `path`, or at the default path if it exists
+pub fn read(path: Option<&Path>) -> Result<CaptureSettings, String> {
+    // a missing config file is only an error when it was asked for explicitly
+    let Some(path) = path
+        .map(Path::to_path_buf)
+        .or_else(|| default_path().filter(|path| path.exists()))
+    else {
+        return Ok(CaptureSettings::default());
+    };
+    let contents = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let file: ConfigFile =
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let invalid =
+        |key: &str, e: &dyn std::fmt::Display| format!("{}: `{}`: {}", path.display(), key, e);
+    let template = file
+        .template
+        .map(|s| s.parse::<PathTemplate>())
+        .transpose()
+        .map_err(|e| invalid("template", &e))?;
+    let store = file
+        .storage
+        .store
+        .map(|s| s.parse::<StoreKind>())
+        .transpose()
+        .map_err(|e| invalid("storage.store", &e))?;
+    Ok(CaptureSettings {
+        path: Some(path),
+        template,
+        store,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn reads_where_captures_are_and_ignores_the_rest() {
+        let path = env::temp_dir().join(format!("panopticon-ics-config-{}.toml", process::id()));
+        fs::write(
+            &path,
+            r#"
+            template = "%Y/%m-%d/%H%M%S_{monitor_id}"
+            interval = 30
+
+            [storage]
+            store = "sqlite"
+            quality = 80
+            "#,
+        )
+        .unwrap();
+        let settings = read(Some(&path)).unwrap();
+        assert_eq!(
+            settings.template.map(|t| t.as_str().to_string()),
+            Some("%Y/%m-%d/%H%M%S_{monitor_id}".to_string())
+        );
+        assert_eq!(settings.store, Some(StoreKind::Sqlite));
+        assert_eq!(settings.path, Some(path.clone()));
+
+        fs::write(&path, "template = \"%H%M%S\"").unwrap();
+        let error = read(Some(&path)).err().unwrap();
+        assert!(error.contains("`template`"), "{}", error);
+        fs::remove_file(&path).unwrap();
+
+        assert!(read(Some(&path)).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use age::secrecy::SecretString;
+use age::{scrypt, x25519};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Lets a passphrase protected identity file be opened without a terminal
+const PASSPHRASE_VAR: &str = "PANOPTICON_PASSPHRASE";
+
+fn is_age_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"age-encryption.org/")
+        || bytes.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+}
+
+/// Reads the private keys (AGE-SECRET-KEY-1...) in an identity file as written by age-keygen.
+/// If the file was itself encrypted with a passphrase (`age -p`), the passphrase is taken
+/// from $PANOPTICON_PASSPHRASE or asked for on the terminal.
+pub fn load_identities(path: &Path) -> Result<Vec<x25519::Identity>, String> {
+    let mut contents = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if is_age_file(&contents) {
+        let passphrase = match std::env::var(PASSPHRASE_VAR) {
+            Ok(passphrase) => passphrase,
+            Err(_) => rpassword::prompt_password(format!("Passphrase for {}: ", path.display()))
+                .map_err(|e| format!("couldn't read passphrase: {}", e))?,
+        };
+        let identity = scrypt::Identity::new(SecretString::from(passphrase));
+        contents = age::decrypt(&identity, &contents)
+            .map_err(|e| format!("couldn't decrypt {}: {}", path.display(), e))?;
+    }
+
+    let contents = String::from_utf8(contents)
+        .map_err(|_| format!("{}: not an age identity file", path.display()))?;
+    let identities = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            x25519::Identity::from_str(line)
+                .map_err(|e| format!("{}: invalid identity: {}", path.display(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if identities.is_empty() {
+        return Err(format!("{}: no identities found", path.display()));
+    }
+    Ok(identities)
+}
+
+/// Decrypts a capture that was encrypted to one of `identities`
+pub fn decrypt(bytes: &[u8], identities: &[x25519::Identity]) -> Result<Vec<u8>, String> {
+    let decryptor = age::Decryptor::new_buffered(bytes).map_err(|e| e.to_string())?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i as &dyn age::Identity))
+        .map_err(|e| e.to_string())?;
+    let mut decrypted = Vec::new();
+    reader
+        .read_to_end(&mut decrypted)
+        .map_err(|e| e.to_string())?;
+    Ok(decrypted)
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Duration, Local, Utc};
+use ics::properties::{DtEnd, DtStart, Summary};
+use ics::{Event, ICalendar, escape_text};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::Snapshot;
+
+const ICS_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A run of consecutive snapshots that all share the same classification
+pub struct ClassifiedSpan {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub classification: String,
+}
+
+fn ics_time(time: DateTime<Local>) -> String {
+    time.with_timezone(&Utc).format(ICS_TIME_FORMAT).to_string()
+}
+
+/// Merges consecutive snapshots with the same classification into spans.
+/// Unclassified snapshots are skipped, and a span is ended early whenever the gap
+/// to the next snapshot is larger than `max_gap` (eg. the daemon was not running).
+pub fn classified_spans(
+    snapshots: &BTreeMap<DateTime<Local>, Snapshot>,
+    max_gap: Duration,
+) -> Vec<ClassifiedSpan> {
+    let mut spans: Vec<ClassifiedSpan> = Vec::new();
+    let mut current: Option<ClassifiedSpan> = None;
+    let mut last_time: Option<DateTime<Local>> = None;
+
+    for (time, snapshot) in snapshots {
+        if let Some(mut span) = current.take() {
+            let prev = last_time.unwrap_or(span.start);
+            if snapshot.classification == span.classification && *time - prev <= max_gap {
+                current = Some(span);
+            } else {
+                // the span lasts until the next snapshot, unless that was too far away
+                span.end = std::cmp::min(*time, prev + max_gap);
+                spans.push(span);
+            }
+        }
+
+        if current.is_none() && !snapshot.classification.is_empty() {
+            current = Some(ClassifiedSpan {
+                start: *time,
+                end: *time,
+                classification: snapshot.classification.clone(),
+            });
+        }
+
+        last_time = Some(*time);
+    }
+
+    if let Some(mut span) = current {
+        span.end = last_time.unwrap_or(span.start) + max_gap;
+        spans.push(span);
+    }
+
+    spans
+}
+
+/// Writes every classified span in `snapshots` to `path` as a VEVENT
+pub fn write_calendar(
+    path: &Path,
+    snapshots: &BTreeMap<DateTime<Local>, Snapshot>,
+    max_gap: Duration,
+) -> io::Result<usize> {
+    let dtstamp = ics_time(Local::now());
+
+    let mut calendar = ICalendar::new("2.0", "-//panopticon//panopticon-ics//EN");
+    let spans = classified_spans(snapshots, max_gap);
+    for span in &spans {
+        let start = ics_time(span.start);
+        let mut event = Event::new(format!("{}@panopticon", start), dtstamp.clone());
+        event.push(DtStart::new(start));
+        event.push(DtEnd::new(ics_time(span.end)));
+        event.push(Summary::new(escape_text(span.classification.clone())));
+        calendar.add_event(event);
+    }
+
+    calendar.save_file(path)?;
+    Ok(spans.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // minutes past nine, so that spans read as minutes
+    fn at(minute: i64) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap() + Duration::minutes(minute)
+    }
+
+    // snapshots taken at each minute with each classification, empty for unlabeled
+    fn snapshots(labels: &[(i64, &str)]) -> BTreeMap<DateTime<Local>, Snapshot> {
+        labels
+            .iter()
+            .map(|&(minute, classification)| (at(minute), Snapshot::labeled(classification)))
+            .collect()
+    }
+
+    // each span as (start minute, end minute, classification)
+    fn spans(labels: &[(i64, &str)]) -> Vec<(i64, i64, String)> {
+        classified_spans(&snapshots(labels), Duration::minutes(5))
+            .into_iter()
+            .map(|span| {
+                (
+                    (span.start - at(0)).num_minutes(),
+                    (span.end - at(0)).num_minutes(),
+                    span.classification,
+                )
+            })
+            .collect()
+    }
+
+    fn span(start: i64, end: i64, classification: &str) -> (i64, i64, String) {
+        (start, end, classification.to_string())
+    }
+
+    #[test]
+    fn merges_neighbouring_snapshots_with_the_same_label() {
+        assert_eq!(
+            spans(&[
+                (0, "work"),
+                (1, "work"),
+                (2, "lunch"),
+                (3, "lunch"),
+                (4, "work")
+            ]),
+            [span(0, 2, "work"), span(2, 4, "lunch"), span(4, 9, "work")]
+        );
+    }
+
+    #[test]
+    fn skips_unlabeled_snapshots() {
+        assert_eq!(
+            spans(&[(0, ""), (1, "work"), (2, ""), (3, ""), (4, "work")]),
+            [span(1, 2, "work"), span(4, 9, "work")]
+        );
+        assert_eq!(spans(&[(0, ""), (1, "")]), []);
+        assert_eq!(spans(&[]), []);
+    }
+
+    #[test]
+    fn ends_spans_at_gaps_longer_than_the_maximum() {
+        // a gap of exactly the maximum still joins
+        assert_eq!(spans(&[(0, "work"), (5, "work")]), [span(0, 10, "work")]);
+        // the span lasts the maximum gap past its last snapshot, not until the next one
+        assert_eq!(
+            spans(&[(0, "work"), (20, "work"), (60, "lunch")]),
+            [
+                span(0, 5, "work"),
+                span(20, 25, "work"),
+                span(60, 65, "lunch")
+            ]
+        );
+    }
+}
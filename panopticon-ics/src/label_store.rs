@@ -0,0 +1,105 @@
+use chrono::{DateTime, Local, NaiveDate};
+use panopticon_common::sidecar::Sidecar;
+use panopticon_common::store::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::Snapshot;
+
+/// One committed classification, stored as a line of JSON in the day's sidecar file
+#[derive(Serialize, Deserialize)]
+struct Label {
+    time: DateTime<Local>,
+    classification: String,
+}
+
+/// Loads the labels for `day`. A missing sidecar just means nothing was labeled yet.
+pub fn load_day(store: &Store, day: NaiveDate) -> io::Result<BTreeMap<DateTime<Local>, String>> {
+    let labels: Vec<Label> = store.load(Sidecar::Labels, day)?;
+    Ok(labels
+        .into_iter()
+        .map(|label| (label.time, label.classification))
+        .collect())
+}
+
+/// Rewrites the sidecar for `day` with every classified snapshot taken on that day
+pub fn save_day(
+    store: &Store,
+    day: NaiveDate,
+    snapshots: &BTreeMap<DateTime<Local>, Snapshot>,
+) -> io::Result<()> {
+    let labels: Vec<Label> = snapshots
+        .iter()
+        .filter(|(time, snapshot)| time.date_naive() == day && !snapshot.classification.is_empty())
+        .map(|(time, snapshot)| Label {
+            time: *time,
+            classification: snapshot.classification.clone(),
+        })
+        .collect();
+    // replaced all at once so that a crash never leaves a half written sidecar
+    store.replace(Sidecar::Labels, day, &labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use panopticon_common::store::StoreKind;
+    use panopticon_common::template::{DEFAULT_TEMPLATE, PathTemplate};
+    use std::{env, fs, process};
+
+    fn day() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()
+    }
+
+    // snapshots at 9:00, 9:01 and so on, with one on the next day to show it's left alone
+    fn snapshots(classifications: &[&str]) -> BTreeMap<DateTime<Local>, Snapshot> {
+        let nine = Local.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+        let mut snapshots: BTreeMap<_, _> = classifications
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (nine + Duration::minutes(i as i64), Snapshot::labeled(c)))
+            .collect();
+        snapshots.insert(nine + Duration::days(1), Snapshot::labeled("tomorrow"));
+        snapshots
+    }
+
+    // runs `test` against an empty store of each kind
+    fn with_each_store(name: &str, test: impl Fn(&Store)) {
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        for kind in [StoreKind::Files, StoreKind::Sqlite] {
+            let dir = env::temp_dir().join(format!("panopticon-ics-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            test(&Store::open(&dir, kind, &template).unwrap());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn saved_labels_load_back() {
+        with_each_store("labels-round-trip", |store| {
+            let labeled = snapshots(&["work", "", "lunch"]);
+            save_day(store, day(), &labeled).unwrap();
+            let nine = Local.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+            let expected = BTreeMap::from([
+                (nine, "work".to_string()),
+                (nine + Duration::minutes(2), "lunch".to_string()),
+            ]);
+            assert_eq!(load_day(store, day()).unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn emptied_days_count_as_unlabeled() {
+        with_each_store("labels-emptied", |store| {
+            save_day(store, day(), &snapshots(&["work"])).unwrap();
+            assert!(store.contains(Sidecar::Labels, day()).unwrap());
+
+            save_day(store, day(), &snapshots(&[""])).unwrap();
+            assert!(load_day(store, day()).unwrap().is_empty());
+            assert!(!store.contains(Sidecar::Labels, day()).unwrap());
+        });
+    }
+}
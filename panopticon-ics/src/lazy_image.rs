@@ -1,34 +1,94 @@
+use age::x25519;
 use eframe::egui;
-use std::fs;
-use std::path::PathBuf;
+use panopticon_common::format::{self, CaptureFormat};
+use panopticon_common::store::Store;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::crypt;
 
 pub struct LazyImage {
+    store: Store,
+    // relative to the capture directory
     path: PathBuf,
-    img: Option<Result<egui_extras::RetainedImage, String>>,
+    // the keys to decrypt encrypted captures with
+    identities: Rc<[x25519::Identity]>,
+    img: Option<Result<egui::TextureHandle, String>>,
+}
+
+fn decode(
+    store: &Store,
+    path: &Path,
+    identities: &[x25519::Identity],
+) -> Result<image::RgbaImage, String> {
+    let mut bytes = store
+        .read_capture(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if format::is_encrypted(path) {
+        if identities.is_empty() {
+            return Err(format!(
+                "{}: encrypted, pass --identity to view it",
+                path.display()
+            ));
+        }
+        bytes = crypt::decrypt(&bytes, identities)
+            .map_err(|e| format!("{}: couldn't decrypt: {}", path.display(), e))?;
+    }
+    let format = CaptureFormat::from_path(path)
+        .and_then(|f| image::ImageFormat::from_extension(f.extension()))
+        .ok_or_else(|| format!("{}: unknown image format", path.display()))?;
+
+    let image = image::load_from_memory_with_format(&bytes, format).map_err(|e| {
+        if format == image::ImageFormat::Avif && !cfg!(feature = "avif") {
+            format!(
+                "{}: AVIF decoding requires building panopticon-ics with --features avif",
+                path.display()
+            )
+        } else {
+            format!("{}: {}", path.display(), e)
+        }
+    })?;
+
+    Ok(image.into_rgba8())
 }
 
 impl LazyImage {
-    pub fn new(path: PathBuf) -> LazyImage {
-        LazyImage { path, img: None }
+    pub fn new(store: Store, path: PathBuf, identities: Rc<[x25519::Identity]>) -> LazyImage {
+        LazyImage {
+            store,
+            path,
+            identities,
+            img: None,
+        }
     }
 
     pub fn show_max_size(&mut self, ui: &mut egui::Ui, size: egui::epaint::Vec2) -> egui::Response {
         let img = self.img.get_or_insert_with(|| {
             // Load the texture only once.
-            egui_extras::RetainedImage::from_image_bytes(
-                self.path.to_string_lossy(),
-                &fs::read(&self.path).unwrap(),
-            )
+            decode(&self.store, &self.path, &self.identities).map(|image| {
+                let image = egui::ColorImage::from_rgba_unmultiplied(
+                    [image.width() as usize, image.height() as usize],
+                    image.as_raw(),
+                );
+                ui.ctx().load_texture(
+                    self.path.to_string_lossy(),
+                    image,
+                    egui::TextureOptions::default(),
+                )
+            })
         });
 
         match img {
-            Ok(img) => img.show_max_size(ui, size),
-            Err(err) => {
-                ui.label(err.clone())
-            }
+            Ok(texture) => ui.add(egui::Image::new(&*texture).max_size(size)),
+            Err(err) => ui.label(err.clone()),
         }
     }
 
+    /// Decodes the capture without keeping it, for when it isn't shown
+    pub fn decode(&self) -> Result<image::RgbaImage, String> {
+        decode(&self.store, &self.path, &self.identities)
+    }
+
     pub fn clear(&mut self) {
         self.img = None;
     }
@@ -1,76 +1,347 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+mod config;
+mod crypt;
+mod ics_export;
+mod label_store;
 mod lazy_image;
+mod timelapse;
 mod timeline_widget;
 
-use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone};
-use clap::Parser;
+use chrono::{DateTime, Local, NaiveDate};
+use clap::{Parser, Subcommand};
 use eframe::egui;
-use sscanf::scanf;
-use std::collections::{btree_map::Entry, BTreeMap};
-use std::fs;
+use panopticon_common::scan::Capture;
+use panopticon_common::sidecar::{
+    PauseEvent, Sidecar, SuppressedEntry, Suppression, TickMetadata, TickRecord, UnchangedEntry,
+};
+use panopticon_common::store::{Store, StoreKind};
+use panopticon_common::template::{self, PathTemplate};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
 use std::ops::Bound::{Excluded, Included, Unbounded};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use lazy_image::LazyImage;
-use timeline_widget::{TimelineMarker, TimelineWidget};
+use timeline_widget::{TimelineMarker, TimelineSpan, TimelineWidget};
 
 #[derive(Parser, Clone)]
 #[clap(name = "panopticon-ics")]
 #[clap(author = "Govind Pimpale <gpimpale29@gmail.com>")]
 #[clap(version = "0.1")]
+#[clap(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 /// Converts panopticon data to an ICS file
 struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// Panopticon image directory
-    dir: String,
+    #[clap(required = true)]
+    dir: Option<String>,
+    /// ICS file to write the classified snapshots to. Defaults to panopticon.ics inside the image directory
+    #[clap(long, short)]
+    output: Option<String>,
+    /// Maximum gap in seconds between two snapshots for them to be merged into the same event
+    #[clap(long, short, default_value = "300")]
+    max_gap: u64,
+    /// The path template the screenshots were taken with, see panopticon --help. Defaults to
+    /// the one in the config file, or to the default template.
+    #[clap(long, short, global = true)]
+    template: Option<PathTemplate>,
+    /// The daemon's config file, to read `template` and `storage.store` from. Defaults to
+    /// $XDG_CONFIG_HOME/panopticon/config.toml, if it exists.
+    #[clap(long, short, global = true)]
+    config: Option<PathBuf>,
+    /// age identity file (eg. from age-keygen) to view encrypted screenshots with. It may be
+    /// encrypted with a passphrase, which is read from $PANOPTICON_PASSPHRASE or asked for.
+    #[clap(long, short, global = true)]
+    identity: Option<PathBuf>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
-    let Opts { dir } = Opts::parse();
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Renders the snapshots of a day or range into an animation or a sequence of images,
+    /// each marked with its time and classification
+    Timelapse(timelapse::TimelapseOpts),
+}
 
-    let mut snapshots = BTreeMap::new();
+fn snapshot_at(
+    snapshots: &mut BTreeMap<DateTime<Local>, Snapshot>,
+    time: DateTime<Local>,
+    afk: bool,
+) -> &mut Snapshot {
+    snapshots.entry(time).or_insert_with(|| Snapshot {
+        screenshots: BTreeMap::new(),
+        afk,
+        classification: String::new(),
+        metadata: None,
+        suppression: None,
+        errors: Vec::new(),
+    })
+}
 
-    // parse each day folder
-    for maybe_day_path in fs::read_dir(dir)? {
-        let day_path = maybe_day_path?;
-        let day = NaiveDate::parse_from_str(&day_path.file_name().to_string_lossy(), "%Y-%m-%d")?;
-        // parse each snapshot
-        for maybe_snapshot_path in fs::read_dir(day_path.path())? {
-            let snapshot_path = maybe_snapshot_path?;
-            let input_data = snapshot_path.file_name().to_string_lossy().to_string();
-            let (hms, screen, afk_str) =
-                scanf!(input_data, "{}_screen-{}{}", str, u64, str).map_err(|e| e.to_string())?;
-
-            let afk = afk_str == "_AFK.png";
-
-            // get real time
-            let time = match Local
-                .from_local_datetime(&day.and_time(NaiveTime::parse_from_str(hms, "%H:%M:%S")?))
-            {
-                chrono::LocalResult::None => Err(format!(
-                    "No valid time corresponds to path {:?}/{:?}",
-                    day_path.file_name().to_string_lossy(),
-                    snapshot_path.file_name().to_string_lossy()
-                ))?,
-                chrono::LocalResult::Single(t) => t,
-                chrono::LocalResult::Ambiguous(t, _) => t,
-            };
+fn insert_screenshot(
+    snapshots: &mut BTreeMap<DateTime<Local>, Snapshot>,
+    time: DateTime<Local>,
+    monitor_id: u32,
+    afk: bool,
+    lazy_image: LazyImage,
+) {
+    snapshot_at(snapshots, time, afk)
+        .screenshots
+        .insert(u64::from(monitor_id), lazy_image);
+}
 
-            // create image
-            let lazy_image = LazyImage::new(snapshot_path.path());
-            match snapshots.entry(time) {
-                Entry::Vacant(x) => {
-                    x.insert(Snapshot {
-                        screenshots: BTreeMap::from([(screen, lazy_image)]),
-                        afk,
-                        classification: String::new(),
-                    });
-                }
-                Entry::Occupied(mut x) => {
-                    x.get_mut().screenshots.insert(screen, lazy_image);
+// the stretches of time the daemon was paused. A pause that was never resumed (eg. since
+// the daemon was killed) ends when it was due to, or at the next snapshot.
+fn paused_spans(
+    mut events: Vec<PauseEvent>,
+    snapshots: &BTreeMap<DateTime<Local>, Snapshot>,
+) -> Vec<(DateTime<Local>, DateTime<Local>)> {
+    events.sort_by_key(|e| e.time());
+    let mut spans = Vec::new();
+    let mut open = None;
+    for event in events {
+        match event {
+            // pausing again while paused extends the pause
+            PauseEvent::Pause { time, until } => {
+                open = Some((open.map_or(time, |(start, _)| start), until))
+            }
+            PauseEvent::Resume { time } => {
+                if let Some((start, _)) = open.take() {
+                    spans.push((start, time));
                 }
             }
         }
     }
+    if let Some((start, until)) = open {
+        let next_snapshot = snapshots
+            .range((Excluded(start), Unbounded))
+            .next()
+            .map(|(t, _)| *t);
+        let end = [until, next_snapshot]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or_else(Local::now);
+        spans.push((start, end));
+    }
+    spans
+}
+
+// tells the user that the `sidecar` of `day` is left out of the history
+fn warn_unreadable(store: &Store, sidecar: Sidecar, day: NaiveDate, error: &io::Error) {
+    eprintln!(
+        "skipping the {} of {} in {}: {}",
+        sidecar.name(),
+        day,
+        store.location(sidecar, day).display(),
+        error
+    );
+}
+
+// the entries of the `sidecar` of `day`, or none if it can't be read, so that one bad sidecar
+// doesn't keep the rest of the history from loading
+fn load_or_warn<T: for<'de> Deserialize<'de>>(
+    store: &Store,
+    sidecar: Sidecar,
+    day: NaiveDate,
+) -> Vec<T> {
+    store.load(sidecar, day).unwrap_or_else(|e| {
+        warn_unreadable(store, sidecar, day, &e);
+        Vec::new()
+    })
+}
+
+/// Everything that was recorded in a capture directory
+struct History {
+    snapshots: BTreeMap<DateTime<Local>, Snapshot>,
+    // when the daemon was paused, as opposed to not running
+    paused: Vec<(DateTime<Local>, DateTime<Local>)>,
+    // when the daemon should have taken screenshots but didn't, eg. while suspended
+    missed: Vec<(DateTime<Local>, DateTime<Local>)>,
+}
+
+fn load_history(
+    store: &Store,
+    template: &PathTemplate,
+    identities: &Rc<[age::x25519::Identity]>,
+) -> Result<History, Box<dyn std::error::Error + 'static>> {
+    let mut snapshots = BTreeMap::new();
+
+    // parse each snapshot, anything that doesn't follow the template (eg. sidecars) is skipped
+    let captures = store.find_captures(template)?;
+    let found_captures = !captures.is_empty();
+    for Capture { path, parsed } in captures {
+        let time = parsed.local_time();
+
+        // create image
+        let lazy_image = LazyImage::new(store.clone(), path, identities.clone());
+        insert_screenshot(
+            &mut snapshots,
+            time,
+            parsed.monitor_id,
+            parsed.afk,
+            lazy_image,
+        );
+    }
+
+    let mut days: BTreeSet<NaiveDate> = snapshots.keys().map(|t| t.date_naive()).collect();
+    days.extend(store.days()?);
+    // captures saved under another template are skipped without a trace, leaving only the
+    // sidecars to show that the daemon ran
+    if !found_captures && !days.is_empty() {
+        eprintln!(
+            "none of the captures match the template {}, pass the daemon's with --template \
+             or --config",
+            template.as_str()
+        );
+    }
+
+    // captures that were skipped since the screen hadn't changed show the earlier image
+    for day in &days {
+        let entries: Vec<UnchangedEntry> = load_or_warn(store, Sidecar::Unchanged, *day);
+        for entry in entries {
+            let lazy_image = LazyImage::new(store.clone(), entry.same_as, identities.clone());
+            insert_screenshot(
+                &mut snapshots,
+                entry.time,
+                entry.monitor_id,
+                entry.afk,
+                lazy_image,
+            );
+        }
+    }
+
+    // ticks whose screenshots were skipped still show up, so that they can be labeled
+    for day in &days {
+        let entries: Vec<SuppressedEntry> = load_or_warn(store, Sidecar::Suppressed, *day);
+        for entry in entries {
+            snapshot_at(&mut snapshots, entry.time, false).suppression = Some(entry.suppression);
+        }
+    }
+
+    // attach what the user was doing to each snapshot
+    for day in &days {
+        let entries: Vec<TickMetadata> = load_or_warn(store, Sidecar::Metadata, *day);
+        for metadata in entries {
+            if let Some(snapshot) = snapshots.get_mut(&metadata.time) {
+                snapshot.metadata = Some(metadata);
+            }
+        }
+    }
+
+    let mut pause_events = Vec::new();
+    for day in &days {
+        pause_events.extend(load_or_warn::<PauseEvent>(store, Sidecar::Pauses, *day));
+    }
+    let paused = paused_spans(pause_events, &snapshots);
+
+    // the capture log tells why there are gaps: ticks that failed, and slots that were missed
+    let mut missed = Vec::new();
+    for day in &days {
+        let records: Vec<TickRecord> = load_or_warn(store, Sidecar::Log, *day);
+        for record in records {
+            if let Some(first_missed) = record.first_missed {
+                missed.push((first_missed, record.time));
+            }
+            if !record.errors.is_empty() {
+                snapshot_at(&mut snapshots, record.time, record.afk).errors = record.errors;
+            }
+        }
+    }
+
+    // restore the classifications committed in previous sessions
+    for day in days {
+        let labels = label_store::load_day(store, day).unwrap_or_else(|e| {
+            warn_unreadable(store, Sidecar::Labels, day, &e);
+            BTreeMap::new()
+        });
+        for (time, classification) in labels {
+            if let Some(snapshot) = snapshots.get_mut(&time) {
+                snapshot.classification = classification;
+            }
+        }
+    }
+
+    Ok(History {
+        snapshots,
+        paused,
+        missed,
+    })
+}
+
+// the database the daemon was told to use with --store sqlite, or the files, which have to be
+// what the config file says if it says anything
+fn open_store(
+    dir: &Path,
+    template: &PathTemplate,
+    settings: &config::CaptureSettings,
+) -> Result<Store, String> {
+    let store = Store::detect(dir, template).map_err(|e| e.to_string())?;
+    if let (Some(path), Some(kind)) = (&settings.path, settings.store)
+        && kind != store.kind()
+    {
+        let found = match store.kind() {
+            StoreKind::Files => "no database",
+            StoreKind::Sqlite => "a database",
+        };
+        return Err(format!(
+            "{}: `storage.store` is {}, but {} has {}",
+            path.display(),
+            kind,
+            dir.display(),
+            found
+        ));
+    }
+    Ok(store)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
+    let Opts {
+        command,
+        dir,
+        output,
+        max_gap,
+        template,
+        config,
+        identity,
+    } = Opts::parse();
+
+    let settings = config::read(config.as_deref())?;
+    let template = match template.or_else(|| settings.template.clone()) {
+        Some(template) => template,
+        None => PathTemplate::new(template::DEFAULT_TEMPLATE)?,
+    };
+
+    let identities: Rc<[age::x25519::Identity]> = match identity {
+        Some(path) => crypt::load_identities(&path)?.into(),
+        None => Rc::new([]),
+    };
+
+    if let Some(Command::Timelapse(opts)) = command {
+        let dir = PathBuf::from(&opts.dir);
+        let store = open_store(&dir, &template, &settings)?;
+        let history = load_history(&store, &template, &identities)?;
+        let frames = timelapse::render(&history.snapshots, &opts)?;
+        println!("Wrote {} frames to {}", frames, opts.output.display());
+        return Ok(());
+    }
+
+    // clap insists on the directory when there's no subcommand
+    let dir = PathBuf::from(dir.unwrap_or_default());
+    let ics_path = output
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join("panopticon.ics"));
+
+    let store = open_store(&dir, &template, &settings)?;
+
+    let History {
+        snapshots,
+        paused,
+        missed,
+    } = load_history(&store, &template, &identities)?;
 
     eframe::run_native(
         "panopticon-ics",
@@ -83,6 +354,11 @@ fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
                     .map(|x| *x.0)
                     .unwrap_or(Local::now()),
                 snapshots,
+                paused,
+                missed,
+                store,
+                ics_path,
+                chrono::Duration::seconds(max_gap as i64),
             )))
         }),
     )?;
@@ -94,13 +370,44 @@ struct Snapshot {
     screenshots: BTreeMap<u64, LazyImage>,
     afk: bool,
     classification: String,
+    metadata: Option<TickMetadata>,
+    // set when a blocklisted window had the focus
+    suppression: Option<Suppression>,
+    // what went wrong during the tick, according to the capture log
+    errors: Vec<String>,
+}
+
+#[cfg(test)]
+impl Snapshot {
+    /// A snapshot without screenshots, classified as `classification`
+    fn labeled(classification: &str) -> Snapshot {
+        Snapshot {
+            screenshots: BTreeMap::new(),
+            afk: false,
+            classification: classification.to_string(),
+            metadata: None,
+            suppression: None,
+            errors: Vec::new(),
+        }
+    }
 }
 
 struct MyApp {
     // variables that capture relatively permanent state
     snapshots: BTreeMap<DateTime<Local>, Snapshot>,
+    // when the daemon was paused, as opposed to not running
+    paused: Vec<(DateTime<Local>, DateTime<Local>)>,
+    // when the daemon should have taken screenshots but didn't, eg. while suspended
+    missed: Vec<(DateTime<Local>, DateTime<Local>)>,
     current_time: DateTime<Local>,
     zoom_multipler: u32,
+    store: Store,
+    ics_path: PathBuf,
+    max_gap: chrono::Duration,
+    export_status: Result<String, String>,
+    save_error: Option<String>,
+    // labels were saved since the calendar was last written, so closing the viewer rewrites it
+    unexported_labels: bool,
 
     // variables that capture per snapshot state
     hint_text: String,
@@ -114,17 +421,48 @@ impl MyApp {
     pub fn new(
         current_time: DateTime<Local>,
         snapshots: BTreeMap<DateTime<Local>, Snapshot>,
+        paused: Vec<(DateTime<Local>, DateTime<Local>)>,
+        missed: Vec<(DateTime<Local>, DateTime<Local>)>,
+        store: Store,
+        ics_path: PathBuf,
+        max_gap: chrono::Duration,
     ) -> Self {
         MyApp {
             zoom_multipler: 1,
             current_time,
             snapshots,
+            paused,
+            missed,
+            store,
+            ics_path,
+            max_gap,
+            export_status: Ok(String::new()),
+            save_error: None,
+            unexported_labels: false,
             hint_text: String::new(),
             currently_visible_shortcuts: Vec::new(),
             scroll_dirty: false,
         }
     }
 
+    fn save_labels(&mut self, time: DateTime<Local>) {
+        self.save_error = label_store::save_day(&self.store, time.date_naive(), &self.snapshots)
+            .err()
+            .map(|e| format!("Couldn't save labels: {}", e));
+        self.unexported_labels = true;
+    }
+
+    fn export_ics(&mut self) {
+        self.export_status =
+            match ics_export::write_calendar(&self.ics_path, &self.snapshots, self.max_gap) {
+                Ok(n) => {
+                    self.unexported_labels = false;
+                    Ok(format!("Wrote {} events to {}", n, self.ics_path.display()))
+                }
+                Err(e) => Err(format!("Couldn't write {}: {}", self.ics_path.display(), e)),
+            };
+    }
+
     fn on_new_snapshot(&mut self) {
         // the hint text is the previous snapshot classification
         self.hint_text = self
@@ -179,6 +517,16 @@ impl MyApp {
 }
 
 impl eframe::App for MyApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if !self.unexported_labels {
+            return;
+        }
+        self.export_ics();
+        if let Err(e) = &self.export_status {
+            eprintln!("{}", e);
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::SidePanel::left("Calendar")
             .resizable(false)
@@ -201,6 +549,26 @@ impl eframe::App for MyApp {
                         ui.label(egui::RichText::new("<Enter>").code());
                         ui.label("commit classification");
                     });
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(egui::RichText::new("- ").strong());
+                        ui.label("shaded blue: panopticon was paused");
+                    });
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(egui::RichText::new("- ").strong());
+                        ui.label("shaded red: screenshots were missed, red line: a tick failed");
+                    });
+                });
+
+                ui.horizontal_wrapped(|ui| {
+                    if ui.button("Export ICS").clicked() {
+                        self.export_ics();
+                    }
+                    match &self.export_status {
+                        Ok(status) => ui.label(egui::RichText::new(status).small()),
+                        Err(error) => {
+                            ui.colored_label(egui::Color32::RED, egui::RichText::new(error).small())
+                        }
+                    };
                 });
 
                 ui.heading("Calendar ");
@@ -211,7 +579,7 @@ impl eframe::App for MyApp {
                     self.scroll_dirty = true;
                 }
 
-                let timeline_resp = ui.add(TimelineWidget::new(
+                let timeline = TimelineWidget::new(
                     self.zoom_multipler,
                     &mut self.current_time,
                     self.scroll_dirty,
@@ -222,6 +590,8 @@ impl eframe::App for MyApp {
                                 stroke: egui::Stroke {
                                     color: if !v.classification.is_empty() {
                                         egui::Color32::LIGHT_GREEN
+                                    } else if !v.errors.is_empty() {
+                                        egui::Color32::RED
                                     } else if v.afk {
                                         egui::Color32::LIGHT_YELLOW
                                     } else {
@@ -233,7 +603,22 @@ impl eframe::App for MyApp {
                             },
                         )
                     }),
-                ));
+                )
+                .spans(
+                    self.paused
+                        .iter()
+                        .map(|(start, end)| TimelineSpan {
+                            start: *start,
+                            end: *end,
+                            color: egui::Color32::from_rgba_unmultiplied(100, 150, 255, 40),
+                        })
+                        .chain(self.missed.iter().map(|(start, end)| TimelineSpan {
+                            start: *start,
+                            end: *end,
+                            color: egui::Color32::from_rgba_unmultiplied(255, 80, 80, 40),
+                        })),
+                );
+                let timeline_resp = ui.add(timeline);
                 self.scroll_dirty = false;
                 if timeline_resp.changed() {
                     self.on_new_snapshot();
@@ -258,8 +643,46 @@ impl eframe::App for MyApp {
                                 .heading(),
                         );
                     }
+                    if let Some(suppression) = snapshot.suppression {
+                        ui.label(
+                            egui::RichText::new(match suppression {
+                                Suppression::Skipped => "SUPPRESSED",
+                                Suppression::BlackedOut => "BLACKED OUT",
+                            })
+                            .color(egui::Color32::WHITE)
+                            .background_color(egui::Color32::DARK_RED)
+                            .heading(),
+                        )
+                        .on_hover_text("A blocklisted window had the focus");
+                    }
+                    if let Some(metadata) = &snapshot.metadata {
+                        ui.add_space(20.0);
+                        if let Some(window) = &metadata.window {
+                            ui.label(egui::RichText::new(&window.app_name).strong())
+                                .on_hover_text(format!("PID {}", window.pid));
+                            ui.label(&window.title);
+                        }
+                        if let Some(idle_seconds) = metadata.idle_seconds {
+                            ui.label(egui::RichText::new(format!("idle {}s", idle_seconds)).weak());
+                        }
+                        for monitor in &metadata.monitors {
+                            let g = &monitor.geometry;
+                            ui.label(
+                                egui::RichText::new(format!(
+                                    "{}: {}x{}+{}+{}",
+                                    monitor.name, g.width, g.height, g.x, g.y
+                                ))
+                                .small()
+                                .weak(),
+                            );
+                        }
+                    }
                 });
 
+                for error in &snapshot.errors {
+                    ui.colored_label(egui::Color32::RED, egui::RichText::new(error).small());
+                }
+
                 ui.separator();
                 // keyboard controls
                 let response = ui
@@ -302,10 +725,13 @@ impl eframe::App for MyApp {
                     Ok(user_input_parse) => {
                         if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                             snapshot.classification = user_input_parse;
+                            let committed_time = *time;
+                            let next_time = iter.next().map(|(x, _)| *x);
+                            self.save_labels(committed_time);
                             // if there's a one after, then grab its focus
-                            if let Some((next_time, _)) = iter.next() {
+                            if let Some(next_time) = next_time {
                                 // update pointer
-                                self.current_time = *next_time;
+                                self.current_time = next_time;
                                 self.scroll_dirty = true;
                                 self.on_new_snapshot();
                                 response.request_focus();
@@ -316,6 +742,10 @@ impl eframe::App for MyApp {
                         ui.colored_label(egui::Color32::RED, egui::RichText::new(error).small());
                     }
                 }
+
+                if let Some(error) = &self.save_error {
+                    ui.colored_label(egui::Color32::RED, egui::RichText::new(error).small());
+                }
             }
 
             ui.separator();
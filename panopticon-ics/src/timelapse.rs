@@ -0,0 +1,449 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use clap::Args;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{Delay, Frame, Rgba, RgbaImage};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::Snapshot;
+
+/// How hard the GIF encoder works at picking the palette of each frame, from 1 (best) to 30
+const GIF_SPEED: i32 = 10;
+
+/// The most memory the frames of a WebP timelapse may take, as they're all kept until the end
+const MAX_WEBP_BYTES: u64 = 1 << 30;
+
+#[derive(Args, Clone)]
+pub struct TimelapseOpts {
+    /// Panopticon image directory
+    pub dir: String,
+    /// Where to write the timelapse: an animated .webp or .gif, or a directory that each
+    /// frame is saved to as a numbered PNG
+    pub output: PathBuf,
+    /// The day or time to start at, eg. 2024-03-01 or "2024-03-01 09:00". Defaults to the
+    /// first snapshot.
+    #[clap(long)]
+    pub from: Option<TimeBound>,
+    /// The day or time to end at, with a day including all of it. Defaults to the last
+    /// snapshot.
+    #[clap(long)]
+    pub to: Option<TimeBound>,
+    /// Monitor to show. Defaults to the first one of each snapshot.
+    #[clap(long)]
+    pub monitor: Option<u32>,
+    /// Frames per second of the animation
+    #[clap(long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..=50))]
+    pub fps: u32,
+    /// Width in pixels of the frames, which are never enlarged
+    #[clap(long, default_value = "1280", value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+    /// Leave out the snapshots taken while AFK
+    #[clap(long)]
+    pub skip_afk: bool,
+}
+
+/// Either end of the range of a timelapse, in local time
+#[derive(Clone, Copy, Debug)]
+pub enum TimeBound {
+    Day(NaiveDate),
+    Time(NaiveDateTime),
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        for format in [
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%d %H:%M",
+            "%Y-%m-%dT%H:%M",
+        ] {
+            if let Ok(time) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(TimeBound::Time(time));
+            }
+        }
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(TimeBound::Day)
+            .map_err(|_| format!("expected a day or time like 2024-03-01 09:00, got {}", s))
+    }
+}
+
+impl TimelapseOpts {
+    fn includes(&self, time: NaiveDateTime) -> bool {
+        let after_from = match self.from {
+            None => true,
+            Some(TimeBound::Day(day)) => time.date() >= day,
+            Some(TimeBound::Time(from)) => time >= from,
+        };
+        let before_to = match self.to {
+            None => true,
+            Some(TimeBound::Day(day)) => time.date() <= day,
+            Some(TimeBound::Time(to)) => time <= to,
+        };
+        after_from && before_to
+    }
+}
+
+// nothing is written before the first frame, so that an empty timelapse leaves nothing behind
+enum Sink {
+    // libwebp only assembles an animation once it has every frame, expecting this many
+    Webp(Vec<RgbaImage>, usize),
+    Gif(PathBuf, Option<GifEncoder<BufWriter<fs::File>>>),
+    Images(PathBuf),
+}
+
+impl Sink {
+    fn create(path: &Path, expected_frames: usize) -> Result<Sink, String> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("webp") => Ok(Sink::Webp(Vec::new(), expected_frames)),
+            Some("gif") => Ok(Sink::Gif(path.to_path_buf(), None)),
+            None => Ok(Sink::Images(path.to_path_buf())),
+            Some(other) => Err(format!(
+                "{}: can't write .{} timelapses, use .webp, .gif or a directory",
+                path.display(),
+                other
+            )),
+        }
+    }
+
+    fn push(&mut self, index: usize, frame: RgbaImage, fps: u32) -> Result<(), String> {
+        match self {
+            Sink::Webp(frames, expected_frames) => {
+                let bytes = u64::from(frame.width()) * u64::from(frame.height()) * 4;
+                if index == 0 && *expected_frames as u64 * bytes > MAX_WEBP_BYTES {
+                    return Err(format!(
+                        "{} frames of {}x{} take more than {} MiB, and a WebP timelapse keeps \
+                         them all in memory: pick a shorter range or a smaller --width, or \
+                         write a .gif or a directory instead",
+                        expected_frames,
+                        frame.width(),
+                        frame.height(),
+                        MAX_WEBP_BYTES >> 20
+                    ));
+                }
+                frames.push(frame);
+                Ok(())
+            }
+            Sink::Gif(path, encoder) => {
+                let error = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+                let encoder = match encoder {
+                    Some(encoder) => encoder,
+                    None => {
+                        let file = fs::File::create(&*path).map_err(|e| error(&e))?;
+                        let mut created =
+                            GifEncoder::new_with_speed(BufWriter::new(file), GIF_SPEED);
+                        created
+                            .set_repeat(Repeat::Infinite)
+                            .map_err(|e| error(&e))?;
+                        encoder.insert(created)
+                    }
+                };
+                encoder
+                    .encode_frame(Frame::from_parts(
+                        frame,
+                        0,
+                        0,
+                        Delay::from_numer_denom_ms(1000, fps),
+                    ))
+                    .map_err(|e| error(&e))
+            }
+            Sink::Images(dir) => {
+                if index == 0 {
+                    fs::create_dir_all(&*dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+                }
+                let path = dir.join(format!("frame-{:05}.png", index));
+                frame
+                    .save(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            }
+        }
+    }
+
+    // removes a GIF that can't be finished, which would be invalid
+    fn discard(self) {
+        if let Sink::Gif(path, Some(encoder)) = self {
+            drop(encoder);
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn finish(self, path: &Path, fps: u32) -> Result<(), String> {
+        let Sink::Webp(frames, _) = self else {
+            // GIF encoders finish the file when dropped
+            return Ok(());
+        };
+        let Some(first) = frames.first() else {
+            return Ok(());
+        };
+        let config = webp::WebPConfig::new()
+            .map_err(|()| "couldn't configure the WebP encoder".to_string())?;
+        let mut encoder = webp::AnimEncoder::new(first.width(), first.height(), &config);
+        encoder.set_loop_count(0);
+        for (index, frame) in frames.iter().enumerate() {
+            let timestamp = (index as u64 * 1000 / u64::from(fps)) as i32;
+            encoder.add_frame(webp::AnimFrame::from_rgba(
+                frame.as_raw(),
+                frame.width(),
+                frame.height(),
+                timestamp,
+            ));
+        }
+        let encoded = encoder
+            .try_encode()
+            .map_err(|e| format!("WebP encoding failed: {:?}", e))?;
+        fs::write(path, &*encoded).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// shrinks `image` to fit in `width` x `height`, centered on black
+fn fit(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        return image.clone();
+    }
+    let scale = f64::min(
+        f64::from(width) / f64::from(image.width()),
+        f64::from(height) / f64::from(image.height()),
+    );
+    let scaled_width = ((f64::from(image.width()) * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((f64::from(image.height()) * scale).round() as u32).clamp(1, height);
+    let scaled = imageops::resize(image, scaled_width, scaled_height, FilterType::Triangle);
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    imageops::replace(
+        &mut canvas,
+        &scaled,
+        i64::from((width - scaled_width) / 2),
+        i64::from((height - scaled_height) / 2),
+    );
+    canvas
+}
+
+// darkens a strip along the bottom of `image` and writes `text` on it
+fn overlay(image: &mut RgbaImage, font: &FontRef, text: &str) {
+    let size = (image.height() as f32 / 30.0).max(12.0);
+    let font = font.as_scaled(PxScale::from(size));
+    let strip = (size * 1.6).ceil() as u32;
+    let top = image.height().saturating_sub(strip);
+    for y in top..image.height() {
+        for x in 0..image.width() {
+            let pixel = image.get_pixel_mut(x, y);
+            for channel in &mut pixel.0[..3] {
+                *channel = (f32::from(*channel) * 0.3) as u8;
+            }
+        }
+    }
+
+    let baseline = top as f32 + (strip as f32 + font.ascent() + font.descent()) / 2.0;
+    let mut x = size / 2.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            x += font.kern(previous, id);
+        }
+        previous = Some(id);
+        let glyph = id.with_scale_and_position(font.scale(), point(x, baseline));
+        x += font.h_advance(id);
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + i64::from(gx);
+            let py = bounds.min.y as i64 + i64::from(gy);
+            if px < 0 || py < 0 || px >= i64::from(image.width()) || py >= i64::from(image.height())
+            {
+                return;
+            }
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            for channel in &mut pixel.0[..3] {
+                let value = f32::from(*channel) + (255.0 - f32::from(*channel)) * coverage;
+                *channel = value.min(255.0) as u8;
+            }
+        });
+    }
+}
+
+fn caption(time: DateTime<Local>, snapshot: &Snapshot) -> String {
+    let mut caption = time.format("%Y-%m-%d %H:%M:%S").to_string();
+    if !snapshot.classification.is_empty() {
+        caption.push_str("   ");
+        caption.push_str(&snapshot.classification);
+    }
+    if snapshot.afk {
+        caption.push_str("   (AFK)");
+    }
+    caption
+}
+
+/// Writes the snapshots selected by `opts` to `opts.output`, returning how many frames it has
+pub fn render(
+    snapshots: &BTreeMap<DateTime<Local>, Snapshot>,
+    opts: &TimelapseOpts,
+) -> Result<usize, String> {
+    let font = FontRef::try_from_slice(epaint_default_fonts::HACK_REGULAR)
+        .map_err(|e| format!("couldn't load the caption font: {}", e))?;
+
+    let selected = snapshots
+        .iter()
+        .filter(|(time, snapshot)| {
+            opts.includes(time.naive_local()) && !(opts.skip_afk && snapshot.afk)
+        })
+        .filter_map(|(time, snapshot)| {
+            let image = match opts.monitor {
+                Some(id) => snapshot.screenshots.get(&u64::from(id)),
+                None => snapshot.screenshots.values().next(),
+            }?;
+            Some((*time, snapshot, image))
+        })
+        .collect::<Vec<_>>();
+    if selected.is_empty() {
+        return Err("no snapshots to render in the given range".to_string());
+    }
+
+    let mut sink = Sink::create(&opts.output, selected.len())?;
+    // every frame gets the size of the first one, so that the animation doesn't jump around
+    let mut size = None;
+    let mut frames = 0;
+    for (time, snapshot, image) in selected {
+        let image = match image.decode() {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("Skipping the snapshot at {}: {}", time, e);
+                continue;
+            }
+        };
+        let (width, height) = *size.get_or_insert_with(|| {
+            let width = opts.width.min(image.width());
+            let height = (u64::from(image.height()) * u64::from(width) / u64::from(image.width()))
+                .max(1) as u32;
+            (width, height)
+        });
+        let mut frame = fit(&image, width, height);
+        overlay(&mut frame, &font, &caption(time, snapshot));
+        if let Err(e) = sink.push(frames, frame, opts.fps) {
+            sink.discard();
+            return Err(e);
+        }
+        frames += 1;
+    }
+
+    if frames == 0 {
+        return Err("none of the snapshots in the given range could be decoded".to_string());
+    }
+    sink.finish(&opts.output, opts.fps)?;
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn opts(from: Option<&str>, to: Option<&str>) -> TimelapseOpts {
+        TimelapseOpts {
+            dir: String::new(),
+            output: PathBuf::from("out.gif"),
+            from: from.map(|s| s.parse().unwrap()),
+            to: to.map(|s| s.parse().unwrap()),
+            monitor: None,
+            fps: 5,
+            width: 1280,
+            skip_afk: false,
+        }
+    }
+
+    #[test]
+    fn parses_days_and_times() {
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert!(matches!("2024-03-01".parse(), Ok(TimeBound::Day(d)) if d == day));
+        for s in [
+            "2024-03-01 09:30",
+            "2024-03-01T09:30",
+            " 2024-03-01 09:30:00 ",
+            "2024-03-01T09:30:00",
+        ] {
+            assert!(
+                matches!(s.parse(), Ok(TimeBound::Time(t)) if t == time("2024-03-01 09:30:00")),
+                "{}",
+                s
+            );
+        }
+        assert!("yesterday".parse::<TimeBound>().is_err());
+        assert!("2024-13-01".parse::<TimeBound>().is_err());
+    }
+
+    #[test]
+    fn a_day_includes_all_of_it() {
+        let opts = opts(Some("2024-03-01"), Some("2024-03-01"));
+        assert!(opts.includes(time("2024-03-01 00:00:00")));
+        assert!(opts.includes(time("2024-03-01 23:59:59")));
+        assert!(!opts.includes(time("2024-02-29 23:59:59")));
+        assert!(!opts.includes(time("2024-03-02 00:00:00")));
+    }
+
+    #[test]
+    fn times_are_inclusive_bounds() {
+        let opts = opts(Some("2024-03-01 09:00"), Some("2024-03-01 17:00"));
+        assert!(!opts.includes(time("2024-03-01 08:59:59")));
+        assert!(opts.includes(time("2024-03-01 09:00:00")));
+        assert!(opts.includes(time("2024-03-01 17:00:00")));
+        assert!(!opts.includes(time("2024-03-01 17:00:01")));
+    }
+
+    #[test]
+    fn missing_bounds_are_open() {
+        let until = opts(None, Some("2024-03-01"));
+        assert!(until.includes(time("1970-01-01 00:00:00")));
+        assert!(!until.includes(time("2024-03-02 00:00:00")));
+        assert!(opts(None, None).includes(time("2099-01-01 00:00:00")));
+    }
+
+    #[test]
+    fn fit_letterboxes_on_black() {
+        let white = Rgba([255, 255, 255, 255]);
+        let black = Rgba([0, 0, 0, 255]);
+        // a square image in a wide frame is centered with black bars left and right
+        let framed = fit(&RgbaImage::from_pixel(100, 100, white), 200, 100);
+        assert_eq!(framed.dimensions(), (200, 100));
+        assert_eq!(*framed.get_pixel(10, 50), black);
+        assert_eq!(*framed.get_pixel(100, 50), white);
+        assert_eq!(*framed.get_pixel(190, 50), black);
+
+        // a wide image is shrunk to the width of the frame
+        let framed = fit(&RgbaImage::from_pixel(400, 100, white), 200, 100);
+        assert_eq!(framed.dimensions(), (200, 100));
+        assert_eq!(*framed.get_pixel(100, 10), black);
+        assert_eq!(*framed.get_pixel(100, 50), white);
+    }
+
+    #[test]
+    fn refuses_webp_timelapses_too_big_for_memory() {
+        let frame = || RgbaImage::new(1280, 720);
+        let path = Path::new("out.webp");
+        // 3.5 MiB each
+        let mut sink = Sink::create(path, 200).unwrap();
+        assert!(sink.push(0, frame(), 5).is_ok());
+        let mut sink = Sink::create(path, 300_000).unwrap();
+        let error = sink.push(0, frame(), 5).unwrap_err();
+        assert!(error.contains("--width"), "{}", error);
+    }
+
+    #[test]
+    fn fit_keeps_images_of_the_frame_size() {
+        let image = RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        assert_eq!(fit(&image, 4, 3), image);
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return)]
+
 use chrono::{DateTime, Duration, DurationRound, Local};
 use eframe::egui;
 use std::collections::BTreeMap;
@@ -17,11 +19,19 @@ pub struct TimelineMarker<'a> {
     pub label: &'a str,
 }
 
+// a shaded stretch of time, eg. while the daemon was paused
+pub struct TimelineSpan {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub color: egui::Color32,
+}
+
 pub struct TimelineWidget<'a, 'b> {
     zoom_multipler: u32,
     selected_time: &'a mut DateTime<Local>,
     scroll_to_selected: bool,
     markers: BTreeMap<DateTime<Local>, TimelineMarker<'b>>,
+    spans: Vec<TimelineSpan>,
 }
 
 impl<'a, 'b> TimelineWidget<'a, 'b> {
@@ -39,15 +49,24 @@ impl<'a, 'b> TimelineWidget<'a, 'b> {
             markers: markers.into_iter().collect(),
             selected_time,
             scroll_to_selected,
+            spans: Vec::new(),
         }
     }
 
+    pub fn spans<I>(mut self, spans: I) -> Self
+    where
+        I: IntoIterator<Item = TimelineSpan>,
+    {
+        self.spans = spans.into_iter().collect();
+        return self;
+    }
+
     // calculate first hour to display
     fn first_hour(&self) -> DateTime<Local> {
         return self
             .markers
             .first_key_value()
-            .map(|x| x.0.clone())
+            .map(|x| *x.0)
             .unwrap_or(*self.selected_time)
             .duration_trunc(Duration::hours(1))
             .unwrap();
@@ -58,7 +77,7 @@ impl<'a, 'b> TimelineWidget<'a, 'b> {
         return self
             .markers
             .last_key_value()
-            .map(|x| x.0.clone())
+            .map(|x| *x.0)
             .unwrap_or(*self.selected_time)
             .duration_trunc(Duration::hours(1))
             .unwrap()
@@ -139,10 +158,26 @@ impl<'a, 'b> TimelineWidget<'a, 'b> {
             }
         }
 
+        let event_marker_x_offset = 75.0;
+
+        // paint spans behind the markers
+        for span in &self.spans {
+            if span.end < first_visible_time || span.start > last_visible_time {
+                continue;
+            }
+            painter.rect_filled(
+                egui::Rect::from_x_y_ranges(
+                    (time_mark_region.left() + event_marker_x_offset)..=time_mark_region.right(),
+                    (time_mark_region.top() + self.get_y_offset(span.start))
+                        ..=(time_mark_region.top() + self.get_y_offset(span.end)),
+                ),
+                0.0,
+                span.color,
+            );
+        }
+
         // paint event markers + text
         {
-            let event_marker_x_offset = 75.0;
-
             let mut prev_y_offset = self.get_y_offset(self.first_hour());
 
             // draw all markers
@@ -173,7 +208,7 @@ impl<'a, 'b> TimelineWidget<'a, 'b> {
                             y: time_mark_region.top() + y_offset - galley.rect.height(),
                         },
                         galley,
-                        marker_data.stroke.color
+                        marker_data.stroke.color,
                     );
                 }
 
@@ -188,23 +223,23 @@ impl<'a, 'b> TimelineWidget<'a, 'b> {
         }
 
         // if we double clicked a mouse on the calendar, set to that time
-        if response.clicked() {
-            if let Some(p) = response.interact_pointer_pos() {
-                // get y  offset wrt time_mark_region
-                let y_offset = p.y - time_mark_region.top();
-                let time = self.get_time(y_offset);
-                // we're fine with accepting anything within 10 pixels either direction
-                let permissible_error = self.pixels_to_hours(10.0);
-
-                // get all times within a range of the true value, and then sort them to see which one is closest
-                if let Some((selected_time, _)) = self
-                    .markers
-                    .range((time - permissible_error)..=(time + permissible_error))
-                    .min_by_key(|(k, _)| (time - *k).abs())
-                {
-                    *self.selected_time = *selected_time;
-                    response.mark_changed();
-                }
+        if response.clicked()
+            && let Some(p) = response.interact_pointer_pos()
+        {
+            // get y  offset wrt time_mark_region
+            let y_offset = p.y - time_mark_region.top();
+            let time = self.get_time(y_offset);
+            // we're fine with accepting anything within 10 pixels either direction
+            let permissible_error = self.pixels_to_hours(10.0);
+
+            // get all times within a range of the true value, and then sort them to see which one is closest
+            if let Some((selected_time, _)) = self
+                .markers
+                .range((time - permissible_error)..=(time + permissible_error))
+                .min_by_key(|(k, _)| (time - *k).abs())
+            {
+                *self.selected_time = *selected_time;
+                response.mark_changed();
             }
         }
 
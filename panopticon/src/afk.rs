@@ -0,0 +1,70 @@
+use chrono::{DateTime, Duration, Local};
+use clap::ValueEnum;
+use panopticon_common::sidecar::{AfkTransition, Sidecar};
+use panopticon_common::store::Store;
+use serde::Deserialize;
+use std::time;
+
+/// What to do while the user is AFK
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AfkPolicy {
+    /// Keep taking screenshots, marked as AFK
+    Keep,
+    /// Take screenshots less often, every --afk-interval seconds
+    Slow,
+    /// Don't take any screenshots
+    Skip,
+}
+
+/// Follows whether the user is AFK, logging and recording every time they leave or come back
+pub struct AfkTracker {
+    store: Store,
+    threshold: time::Duration,
+    afk: bool,
+}
+
+impl AfkTracker {
+    pub fn new(store: Store, threshold: time::Duration) -> AfkTracker {
+        AfkTracker {
+            store,
+            threshold,
+            afk: false,
+        }
+    }
+
+    pub fn is_afk(&self) -> bool {
+        self.afk
+    }
+
+    /// Takes in the idle time measured at `now`, returning whether the user left or came back
+    pub fn observe(&mut self, idle: time::Duration, now: DateTime<Local>) -> bool {
+        let afk = idle > self.threshold;
+        if afk == self.afk {
+            return false;
+        }
+        self.afk = afk;
+
+        // the user left, or came back, when they last touched the mouse or keyboard
+        let time = now - Duration::from_std(idle).unwrap_or_default();
+        if afk {
+            eprintln!("AFK since {}", time.to_rfc3339());
+        } else {
+            eprintln!("back from AFK at {}", time.to_rfc3339());
+        }
+        let transition = AfkTransition { time, afk };
+        if let Err(e) = self
+            .store
+            .append(Sidecar::Afk, time.date_naive(), &transition)
+        {
+            eprintln!(
+                "couldn't record AFK transition in {}: {}",
+                self.store
+                    .location(Sidecar::Afk, time.date_naive())
+                    .display(),
+                e
+            );
+        }
+        true
+    }
+}
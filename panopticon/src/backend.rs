@@ -0,0 +1,198 @@
+use image::{Rgba, RgbaImage};
+use panopticon_common::sidecar::{Geometry, WindowInfo};
+use std::time;
+use user_idle::UserIdle;
+use xcap::{Monitor, Window};
+
+/// The error type backends report, which the capture loop wraps with context
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The identity of a monitor, as reported by the capture backend
+#[derive(Clone, Debug)]
+pub struct MonitorIdentity {
+    pub id: u32,
+    pub name: String,
+    pub primary: bool,
+}
+
+/// The source of screenshots and user activity that the capture loop runs against
+pub trait CaptureBackend {
+    type Monitor;
+
+    /// Lists the monitors that are currently connected
+    fn monitors(&mut self) -> Result<Vec<Self::Monitor>, BackendError>;
+
+    /// Reports the identity of a monitor returned by `monitors`
+    fn identity(&self, monitor: &Self::Monitor) -> Result<MonitorIdentity, BackendError>;
+
+    /// Reports where a monitor returned by `monitors` sits on the virtual desktop
+    fn geometry(&self, monitor: &Self::Monitor) -> Result<Geometry, BackendError>;
+
+    /// Captures the current contents of a monitor
+    fn capture(&mut self, monitor: &Self::Monitor) -> Result<RgbaImage, BackendError>;
+
+    /// Time since the last mouse or keyboard activity
+    fn idle_time(&mut self) -> Result<time::Duration, BackendError>;
+
+    /// The window that currently has the keyboard focus, if any
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, BackendError>;
+}
+
+/// Captures the real screens through xcap
+pub struct XcapBackend;
+
+impl CaptureBackend for XcapBackend {
+    type Monitor = Monitor;
+
+    fn monitors(&mut self) -> Result<Vec<Monitor>, BackendError> {
+        Ok(Monitor::all()?)
+    }
+
+    fn identity(&self, monitor: &Monitor) -> Result<MonitorIdentity, BackendError> {
+        let id = monitor.id()?;
+        let name = monitor.name()?;
+        // only --monitor primary needs to know, so this doesn't keep the monitor from being
+        // identified
+        let primary = monitor.is_primary().unwrap_or_else(|e| {
+            eprintln!(
+                "couldn't tell whether monitor {} is the primary one: {}",
+                id, e
+            );
+            false
+        });
+        Ok(MonitorIdentity { id, name, primary })
+    }
+
+    fn geometry(&self, monitor: &Monitor) -> Result<Geometry, BackendError> {
+        Ok(Geometry {
+            x: monitor.x()?,
+            y: monitor.y()?,
+            width: monitor.width()?,
+            height: monitor.height()?,
+        })
+    }
+
+    fn capture(&mut self, monitor: &Monitor) -> Result<RgbaImage, BackendError> {
+        Ok(monitor.capture_image()?)
+    }
+
+    fn idle_time(&mut self) -> Result<time::Duration, BackendError> {
+        UserIdle::get_time()
+            .map(|idle| time::Duration::from_millis(idle.as_milliseconds() as u64))
+            .map_err(|e| e.cause.into())
+    }
+
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, BackendError> {
+        // windows that disappear while we look at them are simply skipped
+        let Some(window) = Window::all()?
+            .into_iter()
+            .find(|w| w.is_focused().unwrap_or(false))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(WindowInfo {
+            title: window.title()?,
+            app_name: window.app_name()?,
+            pid: window.pid()?,
+            geometry: Some(Geometry {
+                x: window.x()?,
+                y: window.y()?,
+                width: window.width()?,
+                height: window.height()?,
+            }),
+        }))
+    }
+}
+
+/// A headless backend that generates deterministic images, so that the capture loop
+/// can run without a display.
+pub struct SyntheticBackend {
+    monitor_count: u32,
+    width: u32,
+    height: u32,
+    // incremented every time the monitors are listed, ie. once per tick
+    frame: u32,
+    started: time::Instant,
+}
+
+impl SyntheticBackend {
+    pub fn new(monitor_count: u32) -> SyntheticBackend {
+        SyntheticBackend {
+            monitor_count,
+            width: 640,
+            height: 360,
+            frame: 0,
+            started: time::Instant::now(),
+        }
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    type Monitor = u32;
+
+    fn monitors(&mut self) -> Result<Vec<u32>, BackendError> {
+        self.frame = self.frame.wrapping_add(1);
+        Ok((0..self.monitor_count).collect())
+    }
+
+    fn identity(&self, monitor: &u32) -> Result<MonitorIdentity, BackendError> {
+        Ok(MonitorIdentity {
+            id: *monitor,
+            name: format!("synthetic-{}", monitor),
+            primary: *monitor == 0,
+        })
+    }
+
+    fn geometry(&self, monitor: &u32) -> Result<Geometry, BackendError> {
+        // the monitors sit side by side
+        Ok(Geometry {
+            x: (monitor * self.width) as i32,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        })
+    }
+
+    fn capture(&mut self, monitor: &u32) -> Result<RgbaImage, BackendError> {
+        // a gradient tinted per monitor, with a vertical bar that moves every frame
+        let bar = (self.frame * 16) % self.width;
+        let tint = (monitor.wrapping_mul(67) % 256) as u8;
+        Ok(RgbaImage::from_fn(self.width, self.height, |x, y| {
+            if x >= bar && x < bar + 8 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([
+                    (x * 255 / self.width) as u8,
+                    (y * 255 / self.height) as u8,
+                    tint,
+                    255,
+                ])
+            }
+        }))
+    }
+
+    fn idle_time(&mut self) -> Result<time::Duration, BackendError> {
+        // the user touches the mouse every 90 seconds, so that with the default threshold
+        // they are AFK for the last 30 seconds of each round. This follows the clock rather
+        // than the ticks, since ticks may stop while the user is AFK.
+        Ok(time::Duration::from_secs(
+            self.started.elapsed().as_secs() % 90,
+        ))
+    }
+
+    fn focused_window(&mut self) -> Result<Option<WindowInfo>, BackendError> {
+        // pretends the user switches between three windows
+        Ok(Some(WindowInfo {
+            title: format!("Synthetic window {}", self.frame % 3),
+            app_name: "synthetic".to_string(),
+            pid: std::process::id(),
+            // straddles the first two monitors
+            geometry: Some(Geometry {
+                x: self.width as i32 - 160,
+                y: 90,
+                width: 320,
+                height: 180,
+            }),
+        }))
+    }
+}
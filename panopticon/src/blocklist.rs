@@ -0,0 +1,43 @@
+use clap::ValueEnum;
+use panopticon_common::sidecar::WindowInfo;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// What to do when a blocklisted window has the focus
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockAction {
+    /// Don't take any screenshots
+    Skip,
+    /// Take the screenshots with the window blacked out
+    Blackout,
+}
+
+/// Parses a case insensitive blocklist pattern
+pub fn parse_pattern(s: &str) -> Result<Regex, String> {
+    RegexBuilder::new(s)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Windows that must never show up in a screenshot, eg. password managers
+#[derive(Clone, Debug, Default)]
+pub struct Blocklist {
+    pub titles: Vec<Regex>,
+    pub app_names: Vec<Regex>,
+}
+
+impl Blocklist {
+    pub fn is_empty(&self) -> bool {
+        self.titles.is_empty() && self.app_names.is_empty()
+    }
+
+    /// The first pattern that `window` matches, if any
+    pub fn matching(&self, window: &WindowInfo) -> Option<&Regex> {
+        self.titles
+            .iter()
+            .find(|r| r.is_match(&window.title))
+            .or_else(|| self.app_names.iter().find(|r| r.is_match(&window.app_name)))
+    }
+}
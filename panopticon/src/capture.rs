@@ -0,0 +1,675 @@
+use age::x25519;
+use chrono::{DateTime, Local};
+use image::{Rgba, RgbaImage, imageops};
+use panopticon_common::format::{self, CaptureFormat};
+use panopticon_common::scan;
+use panopticon_common::sidecar::{
+    CaptureStats, Geometry, MonitorMetadata, Sidecar, UnchangedEntry,
+};
+use panopticon_common::store::Store;
+use panopticon_common::template::{self, PathTemplate};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{fs, thread, time};
+
+use crate::backend::CaptureBackend;
+use crate::encode;
+use crate::error::CaptureError;
+use crate::monitors::MonitorFilter;
+use crate::phash::{self, ChangeDetector};
+use crate::transform::Transform;
+
+/// How many times a failing capture step is attempted before giving up on it for this tick
+const MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled after every further failure
+const INITIAL_BACKOFF: time::Duration = time::Duration::from_millis(250);
+
+/// What happened while capturing the monitors of a tick
+#[derive(Default)]
+pub struct TickOutcome {
+    /// The monitors that were captured successfully
+    pub monitors: Vec<MonitorMetadata>,
+    /// What capturing each of those monitors cost
+    pub stats: Vec<CaptureStats>,
+    pub errors: Vec<CaptureError>,
+}
+
+// runs f until it succeeds, sleeping with exponential backoff between attempts
+fn with_retry<T>(mut f: impl FnMut() -> Result<T, CaptureError>) -> Result<T, CaptureError> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt >= MAX_ATTEMPTS => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "attempt {}/{} failed, retrying: {}",
+                    attempt, MAX_ATTEMPTS, e
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// The monitor id and name a composite capture is saved under, in place of a real monitor's.
+/// No backend hands out this id, so composites never mix with the captures of a monitor.
+pub const COMPOSITE_MONITOR_ID: u32 = u32::MAX;
+pub const COMPOSITE_MONITOR_NAME: &str = "desktop";
+
+/// Which monitors are captured, where a capture goes, what goes into its name besides the
+/// time, and how it is encoded
+pub struct Layout<'a> {
+    pub monitors: &'a MonitorFilter,
+    pub store: &'a Store,
+    pub template: &'a PathTemplate,
+    pub hostname: &'a str,
+    pub format: CaptureFormat,
+    pub quality: u8,
+    /// Captures are encrypted to these keys, unless there are none
+    pub recipients: &'a [x25519::Recipient],
+    pub transform: Transform,
+    /// Whether the monitors are stitched into a single capture of the whole desktop
+    pub composite: bool,
+}
+
+// paints the part of `window` (in virtual desktop coordinates) that is on the monitor black
+fn black_out(image: &mut RgbaImage, monitor: &Geometry, window: &Geometry) {
+    // the image can have more pixels than the monitor has logical points, eg. on HiDPI screens
+    let scale_x = f64::from(image.width()) / f64::from(monitor.width.max(1));
+    let scale_y = f64::from(image.height()) / f64::from(monitor.height.max(1));
+    let to_pixels = |offset: i64, scale: f64, max: u32| {
+        ((offset as f64 * scale).round().max(0.0) as u32).min(max)
+    };
+
+    let left = to_pixels(
+        i64::from(window.x) - i64::from(monitor.x),
+        scale_x,
+        image.width(),
+    );
+    let top = to_pixels(
+        i64::from(window.y) - i64::from(monitor.y),
+        scale_y,
+        image.height(),
+    );
+    let right = to_pixels(
+        i64::from(window.x) + i64::from(window.width) - i64::from(monitor.x),
+        scale_x,
+        image.width(),
+    );
+    let bottom = to_pixels(
+        i64::from(window.y) + i64::from(window.height) - i64::from(monitor.y),
+        scale_y,
+        image.height(),
+    );
+
+    for y in top..bottom {
+        for x in left..right {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+}
+
+/// Deletes the temporary files left behind by captures that a previous run didn't finish
+pub fn remove_unfinished(dir: &Path, template: &PathTemplate) {
+    let files = match scan::find_unfinished(dir, template) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!(
+                "couldn't look for unfinished captures in {}: {}",
+                dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    for path in files {
+        match fs::remove_file(&path) {
+            Ok(()) => eprintln!("removed unfinished capture {}", path.display()),
+            Err(e) => eprintln!("couldn't remove {}: {}", path.display(), e),
+        }
+    }
+}
+
+// captures a monitor, with the part of the `blackout` window that is on it painted black
+fn capture_monitor<B: CaptureBackend>(
+    backend: &mut B,
+    monitor: &B::Monitor,
+    blackout: Option<&Geometry>,
+) -> Result<(MonitorMetadata, RgbaImage), CaptureError> {
+    let identity = backend
+        .identity(monitor)
+        .map_err(CaptureError::MonitorInfo)?;
+    let metadata = MonitorMetadata {
+        id: identity.id,
+        name: identity.name.clone(),
+        geometry: backend
+            .geometry(monitor)
+            .map_err(CaptureError::MonitorInfo)?,
+    };
+    let mut image = with_retry(|| {
+        backend
+            .capture(monitor)
+            .map_err(|source| CaptureError::Capture {
+                monitor: identity.clone(),
+                source,
+            })
+    })?;
+    if let Some(window) = blackout {
+        black_out(&mut image, &metadata.geometry, window);
+    }
+    Ok((metadata, image))
+}
+
+// the smallest rectangle of the virtual desktop that covers all of `geometries`
+fn bounds<'a>(geometries: impl Iterator<Item = &'a Geometry> + Clone) -> Geometry {
+    let left = geometries
+        .clone()
+        .map(|g| i64::from(g.x))
+        .min()
+        .unwrap_or(0);
+    let top = geometries
+        .clone()
+        .map(|g| i64::from(g.y))
+        .min()
+        .unwrap_or(0);
+    let right = geometries
+        .clone()
+        .map(|g| i64::from(g.x) + i64::from(g.width))
+        .max()
+        .unwrap_or(0);
+    let bottom = geometries
+        .map(|g| i64::from(g.y) + i64::from(g.height))
+        .max()
+        .unwrap_or(0);
+    Geometry {
+        x: left as i32,
+        y: top as i32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    }
+}
+
+/// Lays the captures of several monitors out on one image the way the monitors are arranged
+/// on the virtual desktop. Every capture is scaled to the pixel density of the sharpest one,
+/// and the parts of the desktop no monitor covers are black.
+fn stitch(captures: &[(Geometry, RgbaImage)]) -> RgbaImage {
+    let scale = captures
+        .iter()
+        .map(|(geometry, image)| f64::from(image.width()) / f64::from(geometry.width.max(1)))
+        .fold(1.0, f64::max);
+    let desktop = bounds(captures.iter().map(|(g, _)| g));
+    let (left, top) = (i64::from(desktop.x), i64::from(desktop.y));
+    let (right, bottom) = (
+        left + i64::from(desktop.width),
+        top + i64::from(desktop.height),
+    );
+    let to_pixels = |points: i64| (points as f64 * scale).round() as i64;
+
+    let mut canvas = RgbaImage::from_pixel(
+        to_pixels(right - left).max(1) as u32,
+        to_pixels(bottom - top).max(1) as u32,
+        Rgba([0, 0, 0, 255]),
+    );
+    for (geometry, image) in captures {
+        let width = to_pixels(i64::from(geometry.width)).max(1) as u32;
+        let height = to_pixels(i64::from(geometry.height)).max(1) as u32;
+        let x = to_pixels(i64::from(geometry.x) - left);
+        let y = to_pixels(i64::from(geometry.y) - top);
+        if image.dimensions() == (width, height) {
+            imageops::replace(&mut canvas, image, x, y);
+        } else {
+            let resized = imageops::resize(image, width, height, imageops::FilterType::CatmullRom);
+            imageops::replace(&mut canvas, &resized, x, y);
+        }
+    }
+    canvas
+}
+
+// saves the capture of monitor `id`, unless it looks the same as the last one saved of it
+fn save(
+    image: RgbaImage,
+    id: u32,
+    name: &str,
+    layout: &Layout,
+    change_detector: &mut Option<ChangeDetector>,
+    time: DateTime<Local>,
+    afk: bool,
+) -> Result<CaptureStats, CaptureError> {
+    let image = layout.transform.apply(image);
+
+    // skip saving the capture if the screen still looks like the last one saved
+    let hash = change_detector.as_ref().map(|_| phash::dhash(&image));
+    if let (Some(detector), Some(hash)) = (change_detector.as_ref(), hash)
+        && let Some(same_as) = detector.unchanged_since(id, time.date_naive(), hash)
+    {
+        let entry = UnchangedEntry {
+            time,
+            monitor_id: id,
+            afk,
+            same_as: same_as.to_path_buf(),
+        };
+        layout
+            .store
+            .append(Sidecar::Unchanged, time.date_naive(), &entry)
+            .map_err(|source| CaptureError::Save {
+                path: layout.store.location(Sidecar::Unchanged, time.date_naive()),
+                source,
+            })?;
+        let stats = CaptureStats {
+            monitor_id: id,
+            bytes: 0,
+            encode_ms: 0,
+            unchanged: true,
+        };
+        return Ok(stats);
+    }
+
+    let relative_path = layout.template.render(
+        &time,
+        &template::Fields {
+            monitor_id: id,
+            monitor_name: name,
+            afk,
+            hostname: layout.hostname,
+        },
+    );
+    // append rather than set the extension, the template may contain dots
+    let mut path = OsString::from(relative_path);
+    path.push(".");
+    path.push(layout.format.extension());
+    if !layout.recipients.is_empty() {
+        path.push(".");
+        path.push(format::ENCRYPTED_EXTENSION);
+    }
+    let path = PathBuf::from(path);
+    let location = layout.store.capture_location(&path);
+
+    let encode_start = time::Instant::now();
+    let mut bytes = encode::encode(&image, layout.format, layout.quality).map_err(|source| {
+        CaptureError::Encode {
+            path: location.clone(),
+            source,
+        }
+    })?;
+    if !layout.recipients.is_empty() {
+        bytes =
+            encode::encrypt(&bytes, layout.recipients).map_err(|source| CaptureError::Encrypt {
+                path: location.clone(),
+                source,
+            })?;
+    }
+    let encode_ms = encode_start.elapsed().as_millis() as u64;
+    with_retry(|| {
+        layout
+            .store
+            .save_capture(&path, time.date_naive(), &bytes)
+            .map_err(|source| CaptureError::Save {
+                path: location.clone(),
+                source,
+            })
+    })?;
+
+    if let (Some(detector), Some(hash)) = (change_detector.as_mut(), hash) {
+        detector.saved(id, time.date_naive(), hash, path);
+    }
+    Ok(CaptureStats {
+        monitor_id: id,
+        bytes: bytes.len() as u64,
+        encode_ms,
+        unchanged: false,
+    })
+}
+
+/// Captures every monitor, a failing monitor doesn't stop the others from being captured.
+/// `blackout` is the rectangle of a window to paint black on the captures.
+/// In composite mode the monitors are saved together as one capture of the whole desktop.
+pub fn screenshot_all<B: CaptureBackend>(
+    backend: &mut B,
+    layout: &Layout,
+    change_detector: &mut Option<ChangeDetector>,
+    time: DateTime<Local>,
+    afk: bool,
+    blackout: Option<&Geometry>,
+) -> TickOutcome {
+    let mut outcome = TickOutcome::default();
+    let monitors = match with_retry(|| backend.monitors().map_err(CaptureError::ListMonitors)) {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            outcome.errors.push(e);
+            return outcome;
+        }
+    };
+
+    // the monitors to stitch together in composite mode
+    let mut captures = Vec::new();
+    for monitor in &monitors {
+        // a monitor that can't be told apart from the excluded ones isn't captured either
+        if layout.monitors.is_active() {
+            match backend.identity(monitor) {
+                Ok(identity) if layout.monitors.allows(&identity) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    outcome.errors.push(CaptureError::MonitorInfo(e));
+                    continue;
+                }
+            }
+        }
+        let (metadata, image) = match capture_monitor(backend, monitor, blackout) {
+            Ok(capture) => capture,
+            Err(e) => {
+                outcome.errors.push(e);
+                continue;
+            }
+        };
+        if layout.composite {
+            captures.push((metadata.geometry, image));
+            continue;
+        }
+        match save(
+            image,
+            metadata.id,
+            &metadata.name,
+            layout,
+            change_detector,
+            time,
+            afk,
+        ) {
+            Ok(stats) => {
+                outcome.monitors.push(metadata);
+                outcome.stats.push(stats);
+            }
+            Err(e) => outcome.errors.push(e),
+        }
+    }
+
+    // the composite is recorded as the one monitor it was saved as, covering the desktop
+    if !captures.is_empty() {
+        let metadata = MonitorMetadata {
+            id: COMPOSITE_MONITOR_ID,
+            name: COMPOSITE_MONITOR_NAME.to_string(),
+            geometry: bounds(captures.iter().map(|(g, _)| g)),
+        };
+        let image = stitch(&captures);
+        match save(
+            image,
+            metadata.id,
+            &metadata.name,
+            layout,
+            change_detector,
+            time,
+            afk,
+        ) {
+            Ok(stats) => {
+                outcome.monitors.push(metadata);
+                outcome.stats.push(stats);
+            }
+            Err(e) => outcome.errors.push(e),
+        }
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SyntheticBackend;
+    use crate::monitors::MonitorSelector;
+    use crate::testing::temp_dir;
+    use chrono::{Duration, TimeZone};
+    use panopticon_common::store::{DATABASE_NAME, StoreKind};
+
+    fn start() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap()
+    }
+
+    fn layout<'a>(
+        monitors: &'a MonitorFilter,
+        store: &'a Store,
+        template: &'a PathTemplate,
+    ) -> Layout<'a> {
+        Layout {
+            monitors,
+            store,
+            template,
+            hostname: "host",
+            format: CaptureFormat::Png,
+            quality: 80,
+            recipients: &[],
+            transform: Transform {
+                scale: 1.0,
+                max_width: None,
+                max_height: None,
+                grayscale: false,
+            },
+            composite: false,
+        }
+    }
+
+    // the relative path a PNG capture of monitor `id` is saved under
+    fn capture_path(template: &PathTemplate, time: DateTime<Local>, id: u32, afk: bool) -> PathBuf {
+        let fields = template::Fields {
+            monitor_id: id,
+            monitor_name: "",
+            afk,
+            hostname: "host",
+        };
+        let mut path = template.render(&time, &fields).into_os_string();
+        path.push(".png");
+        PathBuf::from(path)
+    }
+
+    #[test]
+    fn blacks_out_the_window() {
+        let dir = temp_dir("capture-blackout");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        let monitors = MonitorFilter::default();
+        let layout = layout(&monitors, &store, &template);
+        let mut backend = SyntheticBackend::new(2);
+        // straddles both monitors
+        let window = Geometry {
+            x: 600,
+            y: 100,
+            width: 80,
+            height: 50,
+        };
+
+        screenshot_all(
+            &mut backend,
+            &layout,
+            &mut None,
+            start(),
+            false,
+            Some(&window),
+        );
+        let open = |id| {
+            image::open(dir.join(capture_path(&template, start(), id, false)))
+                .unwrap()
+                .to_rgba8()
+        };
+        let (first, second) = (open(0), open(1));
+        let black = Rgba([0, 0, 0, 255]);
+        assert_eq!(*first.get_pixel(600, 100), black);
+        assert_eq!(*first.get_pixel(639, 149), black);
+        assert_ne!(*first.get_pixel(599, 100), black);
+        assert_ne!(*first.get_pixel(620, 150), black);
+        assert_eq!(*second.get_pixel(39, 149), black);
+        assert_ne!(*second.get_pixel(40, 100), black);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_unchanged_captures_instead_of_saving_them() {
+        let dir = temp_dir("capture-unchanged");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        let monitors = MonitorFilter::default();
+        let layout = layout(&monitors, &store, &template);
+        let mut backend = SyntheticBackend::new(2);
+        // every capture counts as the same as the last one
+        let mut change_detector = Some(ChangeDetector::new(64));
+
+        screenshot_all(
+            &mut backend,
+            &layout,
+            &mut change_detector,
+            start(),
+            false,
+            None,
+        );
+        let later = start() + Duration::seconds(1);
+        let outcome = screenshot_all(
+            &mut backend,
+            &layout,
+            &mut change_detector,
+            later,
+            true,
+            None,
+        );
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.monitors.len(), 2);
+        assert!(outcome.stats.iter().all(|s| s.unchanged && s.bytes == 0));
+
+        assert_eq!(store.find_captures(&template).unwrap().len(), 2);
+        let entries: Vec<UnchangedEntry> = store
+            .load(Sidecar::Unchanged, start().date_naive())
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in entries {
+            assert_eq!(entry.time, later);
+            assert!(entry.afk);
+            assert_eq!(
+                entry.same_as,
+                capture_path(&template, start(), entry.monitor_id, false)
+            );
+        }
+
+        // a new day starts over with a capture of its own
+        let tomorrow = start() + Duration::days(1);
+        let outcome = screenshot_all(
+            &mut backend,
+            &layout,
+            &mut change_detector,
+            tomorrow,
+            false,
+            None,
+        );
+        assert!(outcome.stats.iter().all(|s| !s.unchanged));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_monitors_the_filter_leaves_out() {
+        let dir = temp_dir("capture-filter");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        let monitors = MonitorFilter {
+            include: Vec::new(),
+            exclude: vec![MonitorSelector::Primary],
+        };
+        let layout = layout(&monitors, &store, &template);
+        let mut backend = SyntheticBackend::new(3);
+
+        let outcome = screenshot_all(&mut backend, &layout, &mut None, start(), false, None);
+        assert!(outcome.errors.is_empty());
+        let ids: Vec<u32> = outcome.monitors.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert!(
+            !dir.join(capture_path(&template, start(), 0, false))
+                .exists()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn composites_are_saved_as_one_monitor_covering_the_desktop() {
+        let dir = temp_dir("capture-composite");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        let monitors = MonitorFilter::default();
+        let layout = Layout {
+            composite: true,
+            ..layout(&monitors, &store, &template)
+        };
+        let mut backend = SyntheticBackend::new(2);
+
+        let outcome = screenshot_all(&mut backend, &layout, &mut None, start(), false, None);
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.monitors.len(), 1);
+        let monitor = &outcome.monitors[0];
+        assert_eq!(monitor.id, COMPOSITE_MONITOR_ID);
+        assert_eq!(monitor.name, COMPOSITE_MONITOR_NAME);
+        assert_eq!(
+            (monitor.geometry.width, monitor.geometry.height),
+            (1280, 360)
+        );
+        assert_eq!(outcome.stats[0].monitor_id, COMPOSITE_MONITOR_ID);
+
+        let path = capture_path(&template, start(), COMPOSITE_MONITOR_ID, false);
+        let image = image::open(dir.join(path)).unwrap();
+        assert_eq!((image.width(), image.height()), (1280, 360));
+        assert_eq!(store.find_captures(&template).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_every_monitor_under_the_template() {
+        let dir = temp_dir("capture-files");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        let monitors = MonitorFilter::default();
+        let layout = layout(&monitors, &store, &template);
+        let mut backend = SyntheticBackend::new(2);
+
+        for (tick, afk) in [false, true].into_iter().enumerate() {
+            let time = start() + Duration::seconds(tick as i64);
+            let outcome = screenshot_all(&mut backend, &layout, &mut None, time, afk, None);
+            assert!(outcome.errors.is_empty());
+            let ids: Vec<u32> = outcome.monitors.iter().map(|m| m.id).collect();
+            assert_eq!(ids, [0, 1]);
+            assert!(outcome.stats.iter().all(|s| !s.unchanged && s.bytes > 0));
+            for id in ids {
+                let path = dir.join(capture_path(&template, time, id, afk));
+                let image = image::open(&path).unwrap();
+                assert_eq!((image.width(), image.height()), (640, 360));
+            }
+        }
+
+        let captures = store.find_captures(&template).unwrap();
+        assert_eq!(captures.len(), 4);
+        assert_eq!(captures.iter().filter(|c| c.parsed.afk).count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_to_a_database() {
+        let dir = temp_dir("capture-sqlite");
+        let template = PathTemplate::new(template::DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Sqlite, &template).unwrap();
+        let monitors = MonitorFilter::default();
+        let layout = layout(&monitors, &store, &template);
+        let mut backend = SyntheticBackend::new(2);
+
+        let outcome = screenshot_all(&mut backend, &layout, &mut None, start(), false, None);
+        assert!(outcome.errors.is_empty());
+        let path = capture_path(&template, start(), 1, false);
+        let image = image::load_from_memory(&store.read_capture(&path).unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (640, 360));
+        assert_eq!(store.find_captures(&template).unwrap().len(), 2);
+        // nothing but the database is written to the directory
+        for entry in fs::read_dir(&dir).unwrap() {
+            let name = entry.unwrap().file_name();
+            assert!(
+                name.to_string_lossy().starts_with(DATABASE_NAME),
+                "{:?}",
+                name
+            );
+        }
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
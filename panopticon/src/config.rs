@@ -0,0 +1,305 @@
+use age::x25519;
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use panopticon_common::config::default_path;
+use panopticon_common::format::CaptureFormat;
+use panopticon_common::store::StoreKind;
+use panopticon_common::template::PathTemplate;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, de};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::afk::AfkPolicy;
+use crate::blocklist::{self, BlockAction};
+use crate::monitors::MonitorSelector;
+use crate::schedule::ScheduleWindow;
+use crate::{Backend, Opts};
+
+// values that clap parses with FromStr are read from strings the same way
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|list| {
+            list.iter()
+                .map(|s| s.parse().map_err(de::Error::custom))
+                .collect()
+        })
+        .transpose()
+}
+
+fn patterns<'de, D>(deserializer: D) -> Result<Option<Vec<Regex>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|list| {
+            list.iter()
+                .map(|s| blocklist::parse_pattern(s).map_err(de::Error::custom))
+                .collect()
+        })
+        .transpose()
+}
+
+// monitors may be given by their id as a number, as well as by a string
+fn selectors<'de, D>(deserializer: D) -> Result<Option<Vec<MonitorSelector>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Selector {
+        Id(u32),
+        Text(String),
+    }
+
+    Option::<Vec<Selector>>::deserialize(deserializer)?
+        .map(|list| {
+            list.into_iter()
+                .map(|s| match s {
+                    Selector::Id(id) => Ok(MonitorSelector::Id(id)),
+                    Selector::Text(s) => s.parse().map_err(de::Error::custom),
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+/// The contents of the config file. Every key is optional and named like its command line
+/// flag, with the larger settings grouped in tables.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    dir: Option<String>,
+    interval: Option<f32>,
+    jitter: Option<f32>,
+    #[serde(default, deserialize_with = "parsed")]
+    template: Option<PathTemplate>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    schedule: Option<Vec<ScheduleWindow>>,
+    control_socket: Option<PathBuf>,
+    backend: Option<Backend>,
+    #[serde(default)]
+    afk: AfkConfig,
+    #[serde(default)]
+    storage: StorageConfig,
+    #[serde(default)]
+    retention: RetentionConfig,
+    #[serde(default)]
+    blocklist: BlocklistConfig,
+    #[serde(default)]
+    monitors: MonitorsConfig,
+    #[serde(default)]
+    synthetic: SyntheticConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct AfkConfig {
+    disabled: Option<bool>,
+    threshold: Option<u64>,
+    policy: Option<AfkPolicy>,
+    interval: Option<f32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct StorageConfig {
+    #[serde(default, deserialize_with = "parsed")]
+    format: Option<CaptureFormat>,
+    #[serde(default, deserialize_with = "parsed")]
+    store: Option<StoreKind>,
+    quality: Option<u8>,
+    scale: Option<f32>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    grayscale: Option<bool>,
+    unchanged_threshold: Option<u32>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    recipients: Option<Vec<x25519::Recipient>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RetentionConfig {
+    max_age_days: Option<u64>,
+    max_total_mb: Option<u64>,
+    unlabeled_max_age_days: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct BlocklistConfig {
+    #[serde(default, deserialize_with = "patterns")]
+    titles: Option<Vec<Regex>>,
+    #[serde(default, deserialize_with = "patterns")]
+    apps: Option<Vec<Regex>>,
+    action: Option<BlockAction>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct MonitorsConfig {
+    #[serde(default, deserialize_with = "selectors")]
+    include: Option<Vec<MonitorSelector>>,
+    #[serde(default, deserialize_with = "selectors")]
+    exclude: Option<Vec<MonitorSelector>>,
+    composite: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SyntheticConfig {
+    monitors: Option<u32>,
+}
+
+fn read(path: &Path) -> Result<ConfigFile, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Fills in every option of `opts` that wasn't given on the command line from the config file
+/// at `path`, or at the default path if it exists, then checks the options that clap can't
+pub fn apply(opts: &mut Opts, matches: &ArgMatches, path: Option<&Path>) -> Result<(), String> {
+    // a missing config file is only an error when it was asked for explicitly
+    let path = match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_path().filter(|path| path.exists()),
+    };
+    if let Some(path) = &path {
+        layer(opts, matches, path)?;
+    }
+
+    // points at the flag or the config file key an invalid value came from
+    let source = |id: &str, key: &str| match &path {
+        Some(path) if matches.value_source(id) != Some(ValueSource::CommandLine) => {
+            format!("{}: `{}`", path.display(), key)
+        }
+        _ => format!("--{}", id.replace('_', "-")),
+    };
+    if opts.interval <= 0.0 {
+        return Err(format!(
+            "{} must be greater than 0",
+            source("interval", "interval")
+        ));
+    }
+    if opts.afk_interval <= 0.0 {
+        return Err(format!(
+            "{} must be greater than 0",
+            source("afk_interval", "afk.interval")
+        ));
+    }
+    if !(opts.scale > 0.0 && opts.scale <= 1.0) {
+        return Err(format!(
+            "{} must be greater than 0 and at most 1",
+            source("scale", "storage.scale")
+        ));
+    }
+    if opts.format == CaptureFormat::Avif && !cfg!(feature = "avif") {
+        return Err(format!(
+            "{} can't be avif unless panopticon is built with --features avif, and \
+             panopticon-ics too to view the screenshots",
+            source("format", "storage.format")
+        ));
+    }
+    if opts.jitter > opts.interval {
+        return Err(format!(
+            "{} must be less than or equal to the interval",
+            source("jitter", "jitter")
+        ));
+    }
+    Ok(())
+}
+
+fn layer(opts: &mut Opts, matches: &ArgMatches, path: &Path) -> Result<(), String> {
+    let file = read(path)?;
+
+    // the config file can't enforce the ranges that clap does
+    let out_of_range =
+        |key: &str, range: &str| Err(format!("{}: `{}` must be {}", path.display(), key, range));
+    if file
+        .storage
+        .quality
+        .is_some_and(|q| !(1..=100).contains(&q))
+    {
+        return out_of_range("storage.quality", "from 1 to 100");
+    }
+    if file.storage.max_width == Some(0) {
+        return out_of_range("storage.max-width", "at least 1");
+    }
+    if file.storage.max_height == Some(0) {
+        return out_of_range("storage.max-height", "at least 1");
+    }
+
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    macro_rules! layer {
+        ($field:ident, $value:expr) => {
+            if let Some(value) = $value
+                && !from_cli(stringify!($field))
+            {
+                opts.$field = value;
+            }
+        };
+    }
+
+    layer!(dir, file.dir.map(Some));
+    layer!(interval, file.interval);
+    layer!(jitter, file.jitter);
+    layer!(template, file.template);
+    layer!(schedule, file.schedule);
+    layer!(control_socket, file.control_socket.map(Some));
+    layer!(backend, file.backend);
+
+    layer!(no_afk, file.afk.disabled);
+    layer!(afk_threshold, file.afk.threshold);
+    layer!(afk_policy, file.afk.policy);
+    layer!(afk_interval, file.afk.interval);
+
+    layer!(format, file.storage.format);
+    layer!(store, file.storage.store);
+    layer!(quality, file.storage.quality);
+    layer!(scale, file.storage.scale);
+    layer!(max_width, file.storage.max_width.map(Some));
+    layer!(max_height, file.storage.max_height.map(Some));
+    layer!(grayscale, file.storage.grayscale);
+    layer!(
+        unchanged_threshold,
+        file.storage.unchanged_threshold.map(Some)
+    );
+    layer!(recipient, file.storage.recipients);
+
+    layer!(max_age_days, file.retention.max_age_days.map(Some));
+    layer!(max_total_mb, file.retention.max_total_mb.map(Some));
+    layer!(
+        unlabeled_max_age_days,
+        file.retention.unlabeled_max_age_days.map(Some)
+    );
+
+    layer!(block_title, file.blocklist.titles);
+    layer!(block_app, file.blocklist.apps);
+    layer!(block_action, file.blocklist.action);
+
+    layer!(monitor, file.monitors.include);
+    layer!(exclude_monitor, file.monitors.exclude);
+    layer!(composite, file.monitors.composite);
+
+    layer!(synthetic_monitors, file.synthetic.monitors);
+    Ok(())
+}
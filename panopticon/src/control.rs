@@ -0,0 +1,303 @@
+use chrono::{DateTime, Local};
+use panopticon_common::sidecar::{PauseEvent, Sidecar};
+use panopticon_common::store::Store;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time;
+
+/// Where the daemon listens for `panopticon ctl` when no socket is given
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("panopticon.sock")
+}
+
+/// Parses a duration like `90`, `90s`, `30m`, `2h` or `1d`
+pub fn parse_duration(s: &str) -> Result<time::Duration, String> {
+    let (number, unit_seconds) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a duration like 90s, 30m or 2h, got {:?}", s))?;
+    let seconds = number
+        .checked_mul(unit_seconds)
+        .ok_or("duration too long")?;
+    Ok(time::Duration::from_secs(seconds))
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Running,
+    Paused { until: Option<DateTime<Local>> },
+}
+
+/// Whether the daemon is paused, shared between the capture loop and the control socket.
+/// Every pause and resume is recorded in the pauses sidecar.
+pub struct Control {
+    store: Store,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Control {
+    pub fn new(store: Store) -> Control {
+        Control {
+            store,
+            state: Mutex::new(State::Running),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn record(&self, event: PauseEvent) {
+        let day = event.time().date_naive();
+        if let Err(e) = self.store.append(Sidecar::Pauses, day, &event) {
+            eprintln!(
+                "couldn't record pause in {}: {}",
+                self.store.location(Sidecar::Pauses, day).display(),
+                e
+            );
+        }
+    }
+
+    // resumes a timed pause that has run out, recording it as resumed when it ran out
+    fn expire(&self, state: &mut State) {
+        if let State::Paused { until: Some(until) } = *state
+            && until <= Local::now()
+        {
+            *state = State::Running;
+            self.record(PauseEvent::Resume { time: until });
+        }
+    }
+
+    fn pause(&self, duration: Option<time::Duration>) -> Result<String, String> {
+        let now = Local::now();
+        let until = match duration {
+            Some(d) => Some(
+                chrono::Duration::from_std(d)
+                    .ok()
+                    .and_then(|d| now.checked_add_signed(d))
+                    .ok_or("duration too long")?,
+            ),
+            None => None,
+        };
+        let mut state = self.state.lock().unwrap();
+        *state = State::Paused { until };
+        self.record(PauseEvent::Pause { time: now, until });
+        self.changed.notify_all();
+        Ok(describe(*state))
+    }
+
+    fn resume(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        if let State::Paused { .. } = *state {
+            *state = State::Running;
+            self.record(PauseEvent::Resume { time: Local::now() });
+            self.changed.notify_all();
+        }
+        describe(*state)
+    }
+
+    fn status(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        describe(*state)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.expire(&mut state);
+        matches!(*state, State::Paused { .. })
+    }
+
+    /// Blocks until the daemon is no longer paused
+    pub fn wait_while_paused(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            self.expire(&mut state);
+            state = match *state {
+                State::Running => return,
+                State::Paused { until: None } => self.changed.wait(state).unwrap(),
+                State::Paused { until: Some(until) } => {
+                    let timeout = (until - Local::now()).to_std().unwrap_or_default();
+                    self.changed.wait_timeout(state, timeout).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Carries out one request of `panopticon ctl`, returning the reply
+    fn handle(&self, request: &str) -> Result<String, String> {
+        match request.split_whitespace().collect::<Vec<_>>()[..] {
+            ["pause"] => self.pause(None),
+            ["pause", duration] => self.pause(Some(parse_duration(duration)?)),
+            ["resume"] => Ok(self.resume()),
+            ["status"] => Ok(self.status()),
+            _ => Err(format!("unknown request {:?}", request)),
+        }
+    }
+}
+
+fn describe(state: State) -> String {
+    match state {
+        State::Running => "running".to_string(),
+        State::Paused { until: None } => "paused until resumed".to_string(),
+        State::Paused { until: Some(until) } => format!("paused until {}", until.to_rfc3339()),
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::Control;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::{fs, thread, time};
+
+    const CLIENT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    fn serve_client(control: &Control, stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut request = String::new();
+        BufReader::new(&stream).read_line(&mut request)?;
+        // a daemon checking whether this socket is still in use sends nothing
+        if request.trim().is_empty() {
+            return Ok(());
+        }
+        let reply = match control.handle(&request) {
+            Ok(reply) => format!("ok {}", reply),
+            Err(e) => format!("error {}", e),
+        };
+        writeln!(&stream, "{}", reply)
+    }
+
+    /// Answers `panopticon ctl` requests on the socket at `path` from a background thread
+    pub fn listen(control: Arc<Control>, path: &Path) -> io::Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another panopticon is already listening there",
+                ));
+            }
+            // left behind by a panopticon that was killed
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                // a bug in one request mustn't leave the daemon deaf to the next
+                let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                    stream.and_then(|stream| serve_client(&control, stream))
+                }));
+                match served {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => eprintln!("control connection failed: {}", e),
+                    Err(_) => eprintln!("control request failed, see the panic above"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Sends a request to the daemon listening at `path`, returning its reply, which
+    /// starts with `ok` or `error`
+    pub fn send(path: &Path, request: &str) -> io::Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        writeln!(stream, "{}", request)?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim_end().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+mod socket {
+    use super::Control;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the control socket is only supported on unix",
+        )
+    }
+
+    pub fn listen(_control: Arc<Control>, _path: &Path) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn send(_path: &Path, _request: &str) -> io::Result<String> {
+        Err(unsupported())
+    }
+}
+
+pub use socket::{listen, send};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use panopticon_common::store::StoreKind;
+    use panopticon_common::template::{DEFAULT_TEMPLATE, PathTemplate};
+
+    #[test]
+    fn parses_durations_with_and_without_units() {
+        let seconds = |s| parse_duration(s).map(|d| d.as_secs());
+        assert_eq!(seconds("90"), Ok(90));
+        assert_eq!(seconds("90s"), Ok(90));
+        assert_eq!(seconds("30m"), Ok(30 * 60));
+        assert_eq!(seconds("2h"), Ok(2 * 60 * 60));
+        assert_eq!(seconds("1d"), Ok(24 * 60 * 60));
+        assert!(seconds("").is_err());
+        assert!(seconds("h").is_err());
+        assert!(seconds("-5m").is_err());
+        assert!(seconds("2w").is_err());
+    }
+
+    #[test]
+    fn refuses_durations_that_overflow() {
+        let longest = u64::MAX.to_string();
+        assert_eq!(parse_duration(&longest).map(|d| d.as_secs()), Ok(u64::MAX));
+        for s in [
+            "300000000000000d",
+            "6000000000000000h",
+            "18446744073709551615m",
+        ] {
+            assert_eq!(parse_duration(s), Err("duration too long".to_string()));
+        }
+        // too long to even be a number
+        assert!(parse_duration("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn refuses_pauses_too_long_to_end() {
+        let dir = testing::temp_dir("control-overflow");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let control = Control::new(Store::open(&dir, StoreKind::Files, &template).unwrap());
+
+        assert_eq!(
+            control.handle("pause 100000000d"),
+            Err("duration too long".to_string())
+        );
+        assert_eq!(control.status(), "running");
+        assert!(
+            control
+                .handle("pause 2h")
+                .unwrap()
+                .starts_with("paused until ")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use age::x25519;
+#[cfg(feature = "avif")]
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{DynamicImage, ImageEncoder};
+use panopticon_common::format::CaptureFormat;
+use std::io::Write;
+
+use crate::backend::BackendError;
+
+#[cfg(feature = "avif")]
+/// Trades encoding time for file size in the AVIF encoder, from 1 (slowest) to 10 (fastest)
+const AVIF_SPEED: u8 = 8;
+
+/// Encodes a capture in `format`. `quality` ranges from 1 to 100 and is ignored for PNG.
+/// Grayscale captures are stored with a single channel where the format allows it.
+pub fn encode(
+    image: &DynamicImage,
+    format: CaptureFormat,
+    quality: u8,
+) -> Result<Vec<u8>, BackendError> {
+    let mut bytes = Vec::new();
+    match format {
+        CaptureFormat::Png => {
+            PngEncoder::new(&mut bytes).write_image(
+                image.as_bytes(),
+                image.width(),
+                image.height(),
+                image.color().into(),
+            )?;
+        }
+        CaptureFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let image = match image {
+                DynamicImage::ImageLuma8(_) => image.clone(),
+                _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+            };
+            JpegEncoder::new_with_quality(&mut bytes, quality).write_image(
+                image.as_bytes(),
+                image.width(),
+                image.height(),
+                image.color().into(),
+            )?;
+        }
+        CaptureFormat::Webp => {
+            let rgba = image.to_rgba8();
+            let encoded = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height())
+                .encode_simple(false, f32::from(quality))
+                .map_err(|e| format!("webp encoding failed: {:?}", e))?;
+            bytes.extend_from_slice(&encoded);
+        }
+        #[cfg(feature = "avif")]
+        CaptureFormat::Avif => {
+            let rgba = image.to_rgba8();
+            AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, quality).write_image(
+                rgba.as_raw(),
+                rgba.width(),
+                rgba.height(),
+                image::ExtendedColorType::Rgba8,
+            )?;
+        }
+        // config::apply refuses --format avif without the feature
+        #[cfg(not(feature = "avif"))]
+        CaptureFormat::Avif => {
+            return Err("AVIF support requires building panopticon with --features avif".into());
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encrypts an encoded capture so that only the holders of the recipients' keys can read it
+pub fn encrypt(bytes: &[u8], recipients: &[x25519::Recipient]) -> Result<Vec<u8>, BackendError> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(bytes)?;
+    writer.finish()?;
+    Ok(encrypted)
+}
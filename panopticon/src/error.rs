@@ -0,0 +1,84 @@
+use chrono::{DateTime, Local};
+use std::path::PathBuf;
+use std::{fmt, io};
+
+use crate::backend::{BackendError, MonitorIdentity};
+
+/// Everything that can go wrong while taking a round of screenshots
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The list of monitors couldn't be retrieved
+    ListMonitors(BackendError),
+    /// A property of a monitor (eg. its id) couldn't be read
+    MonitorInfo(BackendError),
+    /// A monitor was found but couldn't be captured
+    Capture {
+        monitor: MonitorIdentity,
+        source: BackendError,
+    },
+    /// The captured image couldn't be encoded
+    Encode { path: PathBuf, source: BackendError },
+    /// The encoded image couldn't be encrypted
+    Encrypt { path: PathBuf, source: BackendError },
+    /// The captured image couldn't be saved
+    Save { path: PathBuf, source: io::Error },
+    /// The idle time of the user couldn't be determined
+    IdleTime(BackendError),
+    /// The focused window couldn't be determined
+    FocusedWindow(BackendError),
+    /// The daemon woke up one or more intervals after the tick was due
+    MissedSlots {
+        count: i64,
+        scheduled: DateTime<Local>,
+    },
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::ListMonitors(e) => write!(f, "couldn't list monitors: {}", e),
+            CaptureError::MonitorInfo(e) => write!(f, "couldn't query monitor: {}", e),
+            CaptureError::Capture { monitor, source } => {
+                write!(
+                    f,
+                    "couldn't capture monitor {} ({}): {}",
+                    monitor.id, monitor.name, source
+                )
+            }
+            CaptureError::Encode { path, source } => {
+                write!(f, "couldn't encode {}: {}", path.display(), source)
+            }
+            CaptureError::Encrypt { path, source } => {
+                write!(f, "couldn't encrypt {}: {}", path.display(), source)
+            }
+            CaptureError::Save { path, source } => {
+                write!(f, "couldn't save {}: {}", path.display(), source)
+            }
+            CaptureError::IdleTime(e) => write!(f, "couldn't get idle time: {}", e),
+            CaptureError::FocusedWindow(e) => write!(f, "couldn't get focused window: {}", e),
+            CaptureError::MissedSlots { count, scheduled } => write!(
+                f,
+                "missed {} slots after the tick due at {} (was the system suspended, the clock \
+                 changed or the last tick too slow?)",
+                count,
+                scheduled.to_rfc3339()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CaptureError::ListMonitors(e) => Some(e.as_ref()),
+            CaptureError::MonitorInfo(e) => Some(e.as_ref()),
+            CaptureError::Capture { source, .. } => Some(source.as_ref()),
+            CaptureError::Encode { source, .. } => Some(source.as_ref()),
+            CaptureError::Encrypt { source, .. } => Some(source.as_ref()),
+            CaptureError::Save { source, .. } => Some(source),
+            CaptureError::IdleTime(e) => Some(e.as_ref()),
+            CaptureError::FocusedWindow(e) => Some(e.as_ref()),
+            CaptureError::MissedSlots { .. } => None,
+        }
+    }
+}
@@ -1,19 +1,125 @@
-use chrono::{DateTime, Local};
-use clap::{error::ErrorKind, CommandFactory, Parser};
+mod afk;
+mod backend;
+mod blocklist;
+mod capture;
+mod config;
+mod control;
+mod encode;
+mod error;
+mod monitors;
+mod phash;
+mod retention;
+mod schedule;
+mod service;
+#[cfg(test)]
+mod testing;
+mod timing;
+mod transform;
+
+use chrono::{DateTime, Duration, Local, SubsecRound};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, error::ErrorKind};
+use panopticon_common::format::CaptureFormat;
+use panopticon_common::sidecar::{Sidecar, SuppressedEntry, Suppression, TickMetadata, TickRecord};
+use panopticon_common::store::{Store, StoreKind};
+use panopticon_common::template::{self, PathTemplate};
 use rand::Rng;
-use xcap::Monitor;
-use std::{fs, thread, time};
-use user_idle::UserIdle;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::{thread, time};
+
+use afk::{AfkPolicy, AfkTracker};
+use backend::{CaptureBackend, SyntheticBackend, XcapBackend};
+use blocklist::{BlockAction, Blocklist};
+use capture::{Layout, TickOutcome};
+use control::Control;
+use error::CaptureError;
+use monitors::{MonitorFilter, MonitorSelector};
+use phash::ChangeDetector;
+use retention::RetentionPolicy;
+use schedule::{Schedule, ScheduleWindow};
+use timing::Slots;
+use transform::Transform;
+
+/// How often the retention policy is enforced
+const RETENTION_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// How often the idle time is checked between ticks, to notice the user leaving or coming back
+const AFK_POLL_INTERVAL: Duration = Duration::seconds(5);
+
+/// How long to wait before looking at the schedule again when no window is about to open
+const SCHEDULE_RECHECK_INTERVAL: time::Duration = time::Duration::from_secs(60 * 60);
+
+#[derive(ValueEnum, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    /// Capture the real screens
+    Xcap,
+    /// Generate deterministic images without needing a display
+    Synthetic,
+}
+
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Controls the panopticon that is running
+    Ctl {
+        /// Control socket of the running panopticon, see --control-socket
+        #[clap(long, short)]
+        socket: Option<PathBuf>,
+        #[clap(subcommand)]
+        action: CtlAction,
+    },
+    /// Runs panopticon in the background as a systemd user service, or a launchd agent on macOS
+    Service {
+        #[clap(subcommand)]
+        action: ServiceAction,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+enum ServiceAction {
+    /// Install and start the service, which runs panopticon with the given options
+    Install {
+        /// Options and directory to run panopticon with, as they'd be given to it directly
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Stop and remove the service
+    Uninstall,
+    /// Print whether the service is installed and running
+    Status,
+}
+
+#[derive(Subcommand, Clone)]
+enum CtlAction {
+    /// Stop taking screenshots for a duration (eg. 90s, 30m or 2h), or until resumed
+    Pause {
+        #[clap(value_parser = control::parse_duration)]
+        duration: Option<time::Duration>,
+    },
+    /// Take screenshots again
+    Resume,
+    /// Print whether screenshots are being taken
+    Status,
+}
 
 #[derive(Parser, Clone)]
+#[clap(args_conflicts_with_subcommands = true)]
 #[clap(name = "panopticon")]
 #[clap(author = "Govind Pimpale <gpimpale29@gmail.com>")]
 #[clap(version = "0.1")]
 #[clap(about = "Takes periodic screenshots", long_about = None)]
 struct Opts {
-    /// Target directory to store screenshots in
-    dir: String,
-    /// Interval in seconds between screenshots
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Target directory to store screenshots in. Required unless set in the config file.
+    dir: Option<String>,
+    /// TOML file to read options from, which the command line overrides. Defaults to
+    /// $XDG_CONFIG_HOME/panopticon/config.toml, if it exists.
+    #[clap(long, short)]
+    config: Option<PathBuf>,
+    /// Interval in seconds between screenshots. Screenshots are taken at multiples of the
+    /// interval, eg. on the minute for 60.
     #[clap(long, short, default_value = "60")]
     interval: f32,
     /// Seconds of jitter to add to the screenshot time. Must be less than or equal to interval.
@@ -25,63 +131,570 @@ struct Opts {
     /// Duration in seconds of no mouse or keyboard activity after which the user will be considered AFK
     #[clap(long, short, default_value = "60")]
     afk_threshold: u64,
+    /// Whether to keep taking screenshots while AFK, slow down or skip them
+    #[clap(long, value_enum, default_value = "keep")]
+    afk_policy: AfkPolicy,
+    /// Interval in seconds between screenshots while AFK with --afk-policy slow
+    #[clap(long, default_value = "300")]
+    afk_interval: f32,
+    /// strftime-style template for the path of each screenshot inside dir, without the extension.
+    /// Supports the placeholders {monitor_id}, {monitor_name}, {afk} and {hostname}.
+    #[clap(long, short, default_value = template::DEFAULT_TEMPLATE)]
+    template: PathTemplate,
+    /// Image format to save screenshots in: png, jpeg, webp or avif
+    #[clap(long, short, default_value = "png")]
+    format: CaptureFormat,
+    /// Where to store the screenshots and everything recorded about them: files (one folder
+    /// per day) or sqlite (a single database, panopticon.sqlite3, in the directory)
+    #[clap(long, default_value = "files")]
+    store: StoreKind,
+    /// Quality from 1 to 100 for the lossy formats (jpeg, webp and avif)
+    #[clap(long, short, default_value = "80", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+    /// Shrink screenshots by this factor, from 0 (exclusive) to 1
+    #[clap(long, default_value = "1")]
+    scale: f32,
+    /// Shrink screenshots, keeping their aspect ratio, until they are at most this many pixels wide
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_width: Option<u32>,
+    /// Shrink screenshots, keeping their aspect ratio, until they are at most this many pixels high
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_height: Option<u32>,
+    /// Save screenshots in grayscale
+    #[clap(long)]
+    grayscale: bool,
+    /// Delete days of screenshots older than this many days
+    #[clap(long)]
+    max_age_days: Option<u64>,
+    /// Delete the oldest days of screenshots while they take up more than this many megabytes
+    #[clap(long)]
+    max_total_mb: Option<u64>,
+    /// Delete days of screenshots that were never labeled once they are older than this many days
+    #[clap(long)]
+    unlabeled_max_age_days: Option<u64>,
+    /// Don't save a screenshot when its perceptual hash differs in at most this many bits (out
+    /// of 64) from the last one saved for the monitor, just record that the screen is unchanged
+    #[clap(long)]
+    unchanged_threshold: Option<u32>,
+    /// Encrypt screenshots to this age public key (age1...), so that only its private key can
+    /// view them. May be given several times.
+    #[clap(long)]
+    recipient: Vec<age::x25519::Recipient>,
+    /// Don't capture the screen while the focused window's title matches this case insensitive
+    /// regex, eg. "private browsing". May be given several times.
+    #[clap(long, value_parser = blocklist::parse_pattern)]
+    block_title: Vec<regex::Regex>,
+    /// Don't capture the screen while the focused window belongs to an application whose name
+    /// matches this case insensitive regex, eg. "keepassxc|1password". May be given several times.
+    #[clap(long, value_parser = blocklist::parse_pattern)]
+    block_app: Vec<regex::Regex>,
+    /// Whether to skip the screenshots or black out the window when a blocklisted window has
+    /// the focus
+    #[clap(long, value_enum, default_value = "skip")]
+    block_action: BlockAction,
+    /// Only capture this monitor, given by id, name or "primary". May be given several times.
+    /// All monitors are captured if none is given.
+    #[clap(long)]
+    monitor: Vec<MonitorSelector>,
+    /// Never capture this monitor, given by id, name or "primary". May be given several times.
+    #[clap(long)]
+    exclude_monitor: Vec<MonitorSelector>,
+    /// Stitch the monitors into one screenshot of the whole desktop, laid out the way the
+    /// monitors are arranged, instead of saving one screenshot per monitor
+    #[clap(long)]
+    composite: bool,
+    /// Only take screenshots during this window, eg. "Mon-Fri 09:00-17:30" or "* 22:00-02:00".
+    /// May be given several times. Screenshots are taken at all times if no window is given.
+    #[clap(long)]
+    schedule: Vec<ScheduleWindow>,
+    /// Unix socket to listen on for `panopticon ctl`. Defaults to panopticon.sock in
+    /// $XDG_RUNTIME_DIR, or in the temporary directory.
+    #[clap(long)]
+    control_socket: Option<PathBuf>,
+    /// Where screenshots and user activity come from
+    #[clap(long, value_enum, default_value = "xcap")]
+    backend: Backend,
+    /// Number of monitors the synthetic backend pretends to have
+    #[clap(long, default_value = "2")]
+    synthetic_monitors: u32,
 }
 
-fn screenshot_all(base_dir: String, time: DateTime<Local>, afk: bool) {
-    let monitors = Monitor::all().unwrap();
-    for monitor in monitors {
-        let image = monitor.capture_image().unwrap();
-        let dir = format!("{}/{}", base_dir, time.format("%Y-%m-%d"));
-        fs::create_dir_all(&dir).unwrap();
-        image
-            .save(format!(
-                "{}/{}{}{}.png",
-                dir,
-                time.format("%H:%M:%S"),
-                format!("_screen-{}", monitor.id().unwrap()),
-                if afk { "_AFK" } else { "" }
-            ))
-            .unwrap();
+// appends the errors of a failed tick to the day's error log
+fn record_failures(store: &Store, time: DateTime<Local>, errors: &[CaptureError]) {
+    let result = errors.iter().try_for_each(|e| {
+        let line = format!("{}\t{}", time.to_rfc3339(), e);
+        store.append_line(Sidecar::Errors, time.date_naive(), &line)
+    });
+    if let Err(e) = result {
+        eprintln!(
+            "couldn't record failures in {}: {}",
+            store.location(Sidecar::Errors, time.date_naive()).display(),
+            e
+        );
+    }
+}
+
+// carries out `panopticon service`
+fn service(action: ServiceAction) -> ! {
+    let result = match action {
+        ServiceAction::Install { args } => {
+            // mistakes in the options are easier to fix now than in the service's log
+            let argv = std::iter::once("panopticon".to_string()).chain(args.iter().cloned());
+            match Opts::command().try_get_matches_from(argv) {
+                Ok(matches) if matches.subcommand().is_some() => {
+                    Err("the service can't run a subcommand".to_string())
+                }
+                Ok(_) => service::install(&args),
+                Err(e) => e.exit(),
+            }
+        }
+        ServiceAction::Uninstall => service::uninstall(),
+        ServiceAction::Status => service::status(),
+    };
+    match result {
+        Ok(message) => {
+            println!("{}", message);
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// sends the request of `panopticon ctl` and prints the reply
+fn ctl(socket: Option<PathBuf>, action: CtlAction) -> ! {
+    let socket = socket.unwrap_or_else(control::default_socket_path);
+    let request = match action {
+        CtlAction::Pause { duration: None } => "pause".to_string(),
+        CtlAction::Pause {
+            duration: Some(duration),
+        } => format!("pause {}", duration.as_secs()),
+        CtlAction::Resume => "resume".to_string(),
+        CtlAction::Status => "status".to_string(),
+    };
+    match control::send(&socket, &request) {
+        Ok(reply) => match reply.split_once(' ') {
+            Some(("ok", reply)) => {
+                println!("{}", reply);
+                std::process::exit(0);
+            }
+            _ => {
+                eprintln!("{}", reply.strip_prefix("error ").unwrap_or(&reply));
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!(
+                "couldn't reach panopticon at {} (is it running?): {}",
+                socket.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+enum Wake {
+    Due,
+    ClockTurnedBack,
+    AfkChanged,
+}
+
+// logs which of the connected monitors will be captured, to catch selections that match nothing
+fn report_monitors<B: CaptureBackend>(backend: &mut B, filter: &MonitorFilter) {
+    let monitors = match backend.monitors() {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            eprintln!("couldn't list the monitors: {}", e);
+            return;
+        }
+    };
+    let selected: Vec<String> = monitors
+        .iter()
+        .filter_map(|m| backend.identity(m).ok())
+        .filter(|identity| filter.allows(identity))
+        .map(|identity| format!("{} ({})", identity.name, identity.id))
+        .collect();
+    if selected.is_empty() {
+        eprintln!("warning: none of the connected monitors are selected for capture");
+    } else {
+        eprintln!("capturing monitors: {}", selected.join(", "));
+    }
+}
+
+// sleeps until target, checking every so often whether the user left or came back
+fn wait_until<B: CaptureBackend>(
+    target: DateTime<Local>,
+    backend: &mut B,
+    mut afk_tracker: Option<&mut AfkTracker>,
+) -> Wake {
+    loop {
+        let wake_at = match afk_tracker {
+            Some(_) => target.min(Local::now() + AFK_POLL_INTERVAL),
+            None => target,
+        };
+        if !timing::sleep_until(wake_at) {
+            return Wake::ClockTurnedBack;
+        }
+        // a failing poll is reported by the next tick, which measures the idle time again
+        if let Some(tracker) = afk_tracker.as_deref_mut()
+            && let Ok(idle) = backend.idle_time()
+            && tracker.observe(idle, Local::now())
+        {
+            return Wake::AfkChanged;
+        }
+        if Local::now() >= target {
+            return Wake::Due;
+        }
     }
 }
 
 fn main() {
-    let Opts {
-        dir,
-        interval,
-        jitter,
-        no_afk,
-        afk_threshold,
-    } = Opts::parse();
+    let matches = Opts::command().get_matches();
+    let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    match opts.command {
+        Some(Command::Ctl { socket, action }) => ctl(socket, action),
+        Some(Command::Service { action }) => service(action),
+        None => {}
+    }
 
-    if interval <= 0.0 {
+    let config_path = opts.config.clone();
+    if let Err(e) = config::apply(&mut opts, &matches, config_path.as_deref()) {
         let mut cmd = Opts::command();
-        cmd.error(ErrorKind::InvalidValue, "interval must be greater than 0")
-            .exit();
+        cmd.error(ErrorKind::InvalidValue, e).exit();
     }
 
-    if jitter > interval {
+    if opts.dir.is_none() {
         let mut cmd = Opts::command();
         cmd.error(
-            ErrorKind::InvalidValue,
-            "jitter must be less than or equal to interval",
+            ErrorKind::MissingRequiredArgument,
+            "no directory to store screenshots in, pass DIR or set `dir` in the config file",
         )
         .exit();
     }
 
+    match opts.backend {
+        Backend::Xcap => run(XcapBackend, opts),
+        Backend::Synthetic => run(SyntheticBackend::new(opts.synthetic_monitors), opts),
+    }
+}
+
+fn run<B: CaptureBackend>(mut backend: B, opts: Opts) -> ! {
+    let Opts {
+        dir,
+        interval,
+        jitter,
+        no_afk,
+        afk_threshold,
+        afk_policy,
+        afk_interval,
+        template,
+        format,
+        store,
+        quality,
+        max_age_days,
+        max_total_mb,
+        unlabeled_max_age_days,
+        unchanged_threshold,
+        schedule,
+        control_socket,
+        recipient,
+        block_title,
+        block_app,
+        block_action,
+        monitor,
+        exclude_monitor,
+        composite,
+        scale,
+        max_width,
+        max_height,
+        grayscale,
+        ..
+    } = opts;
+
+    // main makes sure there is a dir, from the command line or the config file
+    let dir = PathBuf::from(dir.expect("dir is required"));
+    // created up front, since ticks that save no screenshot still record their sidecars
+    let store = match Store::open(&dir, store, &template) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!(
+                "couldn't open the {} store in {}: {}",
+                store,
+                dir.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    if let Store::Files(dir) = &store {
+        capture::remove_unfinished(dir, &template);
+    }
+    // the sidecars aren't encrypted, and a window title tells as much as a screenshot
+    let encrypted = !recipient.is_empty();
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    let monitor_filter = MonitorFilter {
+        include: monitor,
+        exclude: exclude_monitor,
+    };
+    if monitor_filter.is_active() {
+        report_monitors(&mut backend, &monitor_filter);
+    }
+    let layout = Layout {
+        monitors: &monitor_filter,
+        store: &store,
+        template: &template,
+        hostname: &hostname,
+        format,
+        quality,
+        recipients: &recipient,
+        transform: Transform {
+            scale,
+            max_width,
+            max_height,
+            grayscale,
+        },
+        composite,
+    };
+    let retention_policy = RetentionPolicy {
+        max_age_days,
+        max_total_bytes: max_total_mb.map(|mb| mb * 1024 * 1024),
+        unlabeled_max_age_days,
+    };
+    let mut last_retention_check: Option<time::Instant> = None;
+    let mut change_detector = unchanged_threshold.map(ChangeDetector::new);
+    let schedule = Schedule::new(schedule);
+    let blocklist = Blocklist {
+        titles: block_title,
+        app_names: block_app,
+    };
+    let slots = Slots::new(interval);
+    let afk_slots = Slots::new(afk_interval);
+    let mut afk_tracker =
+        (!no_afk).then(|| AfkTracker::new(store.clone(), time::Duration::from_secs(afk_threshold)));
+    // forgotten while paused or outside of the schedule, so that those slots don't count as missed
+    let mut last_slot: Option<DateTime<Local>> = None;
     let mut rng = rand::rng();
 
+    let control = Arc::new(Control::new(store.clone()));
+    let control_socket = control_socket.unwrap_or_else(control::default_socket_path);
+    if let Err(e) = control::listen(control.clone(), &control_socket) {
+        eprintln!(
+            "couldn't listen for panopticon ctl on {}: {}",
+            control_socket.display(),
+            e
+        );
+    }
+
     loop {
-        let delay = jitter * rng.random::<f32>();
-        thread::sleep(time::Duration::from_secs_f32(delay));
+        if control.is_paused() {
+            last_slot = None;
+            control.wait_while_paused();
+        }
+
+        let now = Local::now();
+        if !schedule.is_open(now) {
+            last_slot = None;
+            match schedule.next_open(now) {
+                Some(open) => {
+                    eprintln!(
+                        "outside of the schedule, sleeping until {}",
+                        open.to_rfc3339()
+                    );
+                    timing::sleep_until(open);
+                }
+                // no window can open (eg. skipped by a DST change), check again later
+                None => thread::sleep(SCHEDULE_RECHECK_INTERVAL),
+            }
+            continue;
+        }
 
-        let afk = if no_afk {
-            false
+        let afk = afk_tracker.as_ref().is_some_and(|t| t.is_afk());
+        if afk && afk_policy == AfkPolicy::Skip {
+            // nothing to capture until the user comes back
+            last_slot = None;
+            wait_until(now + slots.interval(), &mut backend, afk_tracker.as_mut());
+            continue;
+        }
+        let slots = if afk && afk_policy == AfkPolicy::Slow {
+            afk_slots
         } else {
-            UserIdle::get_time().unwrap().as_seconds() > afk_threshold
+            slots
         };
-        screenshot_all(dir.clone(), Local::now(), afk);
 
-        thread::sleep(time::Duration::from_secs_f32(interval - delay));
+        let slot = match last_slot {
+            Some(last_slot) => last_slot + slots.interval(),
+            None => slots.first_at_or_after(now),
+        };
+        let delay = Duration::milliseconds((jitter * 1000.0 * rng.random::<f32>()) as i64);
+        let target = slot + delay;
+        // under the keep policy the slots stay the same whether or not the user is AFK, so
+        // a transition is only recorded and the tick still waits for its target
+        let wake = loop {
+            match wait_until(target, &mut backend, afk_tracker.as_mut()) {
+                Wake::AfkChanged if afk_policy == AfkPolicy::Keep => continue,
+                wake => break wake,
+            }
+        };
+        match wake {
+            Wake::Due => {}
+            Wake::ClockTurnedBack => {
+                eprintln!("the clock was turned back, rescheduling");
+                last_slot = None;
+                continue;
+            }
+            Wake::AfkChanged => {
+                last_slot = None;
+                continue;
+            }
+        }
+        if control.is_paused() || !schedule.is_open(Local::now()) {
+            continue;
+        }
+
+        let mut errors = Vec::new();
+
+        // after a suspend, a clock change or a tick that took too long, capture right away
+        // for the current slot rather than catching up on the ones that went by
+        let missed = slots.missed(Local::now() - target);
+        if missed > 0 {
+            errors.push(CaptureError::MissedSlots {
+                count: missed,
+                scheduled: target,
+            });
+        }
+        last_slot = Some(slot + slots.interval() * missed as i32);
+
+        let idle_seconds = match &mut afk_tracker {
+            None => None,
+            Some(tracker) => match backend.idle_time() {
+                Ok(idle) => {
+                    tracker.observe(idle, Local::now());
+                    Some(idle.as_secs())
+                }
+                Err(e) => {
+                    // assume the user is present rather than skipping the tick
+                    errors.push(CaptureError::IdleTime(e));
+                    None
+                }
+            },
+        };
+        // the tracker decides, so that the tick and the AFK sidecar never disagree
+        let afk = idle_seconds.is_some() && afk_tracker.as_ref().is_some_and(|t| t.is_afk());
+
+        let (mut window, blocked_by) = match backend.focused_window() {
+            Ok(window) => {
+                let blocked_by = window
+                    .as_ref()
+                    .and_then(|w| blocklist.matching(w))
+                    .map(|pattern| Some(pattern.as_str().to_string()));
+                (window, blocked_by)
+            }
+            Err(e) => {
+                errors.push(CaptureError::FocusedWindow(e));
+                // any window might have the focus, including a blocklisted one
+                (None, (!blocklist.is_empty()).then_some(None))
+            }
+        };
+
+        // the sidecars shouldn't be more precise than the file names, or they wouldn't match
+        let now = Local::now().trunc_subsecs(template.subsec_digits());
+
+        let outcome = match &blocked_by {
+            // the user only just left, nothing is captured until they are back
+            _ if afk && afk_policy == AfkPolicy::Skip => {
+                last_slot = None;
+                TickOutcome::default()
+            }
+            None => {
+                capture::screenshot_all(&mut backend, &layout, &mut change_detector, now, afk, None)
+            }
+            Some(pattern) => {
+                // a window whose position is unknown can't be blacked out
+                let blackout = match block_action {
+                    BlockAction::Blackout => window.as_ref().and_then(|w| w.geometry),
+                    BlockAction::Skip => None,
+                };
+                let entry = SuppressedEntry {
+                    time: now,
+                    suppression: if blackout.is_some() {
+                        Suppression::BlackedOut
+                    } else {
+                        Suppression::Skipped
+                    },
+                    pattern: if encrypted { None } else { pattern.clone() },
+                };
+                if let Err(e) = store.append(Sidecar::Suppressed, now.date_naive(), &entry) {
+                    errors.push(CaptureError::Save {
+                        path: store.location(Sidecar::Suppressed, now.date_naive()),
+                        source: e,
+                    });
+                }
+                // what the blocklisted window is about mustn't leak through the metadata
+                window = None;
+                match blackout {
+                    Some(blackout) => capture::screenshot_all(
+                        &mut backend,
+                        &layout,
+                        &mut change_detector,
+                        now,
+                        afk,
+                        Some(&blackout),
+                    ),
+                    None => TickOutcome::default(),
+                }
+            }
+        };
+        errors.extend(outcome.errors);
+
+        let metadata = TickMetadata {
+            time: now,
+            window: if encrypted { None } else { window },
+            idle_seconds,
+            monitors: outcome.monitors,
+        };
+        if let Err(e) = store.append(Sidecar::Metadata, now.date_naive(), &metadata) {
+            errors.push(CaptureError::Save {
+                path: store.location(Sidecar::Metadata, now.date_naive()),
+                source: e,
+            });
+        }
+
+        let record = TickRecord {
+            scheduled: target,
+            time: now,
+            afk,
+            captures: outcome.stats,
+            missed_slots: missed,
+            first_missed: (missed > 0).then_some(slot),
+            errors: errors.iter().map(|e| e.to_string()).collect(),
+        };
+        if let Err(e) = store.append(Sidecar::Log, now.date_naive(), &record) {
+            errors.push(CaptureError::Save {
+                path: store.location(Sidecar::Log, now.date_naive()),
+                source: e,
+            });
+        }
+
+        if !errors.is_empty() {
+            for e in &errors {
+                eprintln!("{}: {}", now.to_rfc3339(), e);
+            }
+            record_failures(&store, now, &errors);
+        }
+
+        if retention_policy.is_enabled()
+            && last_retention_check.is_none_or(|t| t.elapsed() >= RETENTION_CHECK_INTERVAL)
+        {
+            if let Err(e) =
+                retention::enforce(&store, &template, &retention_policy, now.date_naive())
+            {
+                eprintln!("couldn't enforce retention policy: {}", e);
+            }
+            last_retention_check = Some(time::Instant::now());
+        }
     }
 }
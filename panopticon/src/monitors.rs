@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+use crate::backend::MonitorIdentity;
+
+/// Picks out monitors by their id, their name (case insensitive), or `primary`
+#[derive(Clone, Debug, PartialEq)]
+pub enum MonitorSelector {
+    Primary,
+    Id(u32),
+    Name(String),
+}
+
+impl FromStr for MonitorSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            Err("expected a monitor id, name or \"primary\"".to_string())
+        } else if s.eq_ignore_ascii_case("primary") {
+            Ok(MonitorSelector::Primary)
+        } else if let Ok(id) = s.parse() {
+            Ok(MonitorSelector::Id(id))
+        } else {
+            Ok(MonitorSelector::Name(s.to_string()))
+        }
+    }
+}
+
+impl MonitorSelector {
+    fn matches(&self, monitor: &MonitorIdentity) -> bool {
+        match self {
+            MonitorSelector::Primary => monitor.primary,
+            MonitorSelector::Id(id) => monitor.id == *id,
+            MonitorSelector::Name(name) => monitor.name.eq_ignore_ascii_case(name),
+        }
+    }
+}
+
+/// Which monitors get captured: those matching any of `include`, or all of them if it's
+/// empty, except those matching any of `exclude`
+#[derive(Clone, Debug, Default)]
+pub struct MonitorFilter {
+    pub include: Vec<MonitorSelector>,
+    pub exclude: Vec<MonitorSelector>,
+}
+
+impl MonitorFilter {
+    /// Whether monitors have to be identified before they may be captured
+    pub fn is_active(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    pub fn allows(&self, monitor: &MonitorIdentity) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|s| s.matches(monitor)))
+            && !self.exclude.iter().any(|s| s.matches(monitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(id: u32, name: &str, primary: bool) -> MonitorIdentity {
+        MonitorIdentity {
+            id,
+            name: name.to_string(),
+            primary,
+        }
+    }
+
+    fn filter(include: &[&str], exclude: &[&str]) -> MonitorFilter {
+        let parse = |selectors: &[&str]| selectors.iter().map(|s| s.parse().unwrap()).collect();
+        MonitorFilter {
+            include: parse(include),
+            exclude: parse(exclude),
+        }
+    }
+
+    #[test]
+    fn parses_selectors() {
+        assert_eq!("Primary".parse(), Ok(MonitorSelector::Primary));
+        assert_eq!(" 2 ".parse(), Ok(MonitorSelector::Id(2)));
+        assert_eq!(
+            "HDMI-1".parse(),
+            Ok(MonitorSelector::Name("HDMI-1".to_string()))
+        );
+        assert!(" ".parse::<MonitorSelector>().is_err());
+    }
+
+    #[test]
+    fn an_empty_filter_allows_every_monitor() {
+        let filter = MonitorFilter::default();
+        assert!(!filter.is_active());
+        assert!(filter.allows(&monitor(0, "eDP-1", true)));
+        assert!(filter.allows(&monitor(1, "HDMI-1", false)));
+    }
+
+    #[test]
+    fn includes_only_the_selected_monitors() {
+        let selected = filter(&["primary", "hdmi-1"], &[]);
+        assert!(selected.is_active());
+        assert!(selected.allows(&monitor(0, "eDP-1", true)));
+        assert!(selected.allows(&monitor(1, "HDMI-1", false)));
+        assert!(!selected.allows(&monitor(2, "DP-2", false)));
+    }
+
+    #[test]
+    fn exclusions_win_over_inclusions() {
+        let all_but_one = filter(&[], &["1"]);
+        assert!(all_but_one.is_active());
+        assert!(all_but_one.allows(&monitor(0, "eDP-1", true)));
+        assert!(!all_but_one.allows(&monitor(1, "HDMI-1", false)));
+
+        let primary = filter(&["primary"], &["edp-1"]);
+        assert!(!primary.allows(&monitor(0, "eDP-1", true)));
+    }
+}
@@ -0,0 +1,107 @@
+use chrono::NaiveDate;
+use image::DynamicImage;
+use image::imageops::{self, FilterType};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A 64 bit difference hash: each bit tells whether a pixel of the image shrunk to 9x8
+/// grayscale pixels is brighter than its right neighbour. Small changes to the screen
+/// (eg. a blinking cursor) flip few bits, unlike a cryptographic hash.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let gray = imageops::grayscale(image);
+    let small = imageops::resize(&gray, 9, 8, FilterType::Triangle);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(bit);
+        }
+    }
+    hash
+}
+
+/// Remembers the last capture saved for each monitor, to tell whether a new one differs.
+/// Captures are only compared with those of the same day, so that retention, which prunes
+/// whole days but never today, can't delete a capture that later ones still point to.
+pub struct ChangeDetector {
+    threshold: u32,
+    last_saved: HashMap<u32, (NaiveDate, u64, PathBuf)>,
+}
+
+impl ChangeDetector {
+    /// Captures whose hash differs in at most `threshold` bits from the last saved one
+    /// are considered unchanged
+    pub fn new(threshold: u32) -> ChangeDetector {
+        ChangeDetector {
+            threshold,
+            last_saved: HashMap::new(),
+        }
+    }
+
+    /// The path of the last capture of the monitor on `day`, if the new capture looks the same
+    pub fn unchanged_since(&self, monitor_id: u32, day: NaiveDate, hash: u64) -> Option<&Path> {
+        self.last_saved
+            .get(&monitor_id)
+            .filter(|(last_day, last_hash, _)| {
+                *last_day == day && (last_hash ^ hash).count_ones() <= self.threshold
+            })
+            .map(|(_, _, path)| path.as_path())
+    }
+
+    pub fn saved(&mut self, monitor_id: u32, day: NaiveDate, hash: u64, path: PathBuf) {
+        self.last_saved.insert(monitor_id, (day, hash, path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn gradient(width: u32, height: u32, reversed: bool) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, _| {
+            let value = (x * 255 / width) as u8;
+            let value = if reversed { 255 - value } else { value };
+            Rgba([value, value, value, 255])
+        }))
+    }
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    #[test]
+    fn hashes_look_past_the_size_of_the_image() {
+        assert_eq!(
+            dhash(&gradient(640, 360, false)),
+            dhash(&gradient(320, 180, false))
+        );
+        // every pixel is brighter than its left neighbour, and darker when reversed
+        assert_eq!(dhash(&gradient(640, 360, false)), 0);
+        assert_eq!(dhash(&gradient(640, 360, true)), u64::MAX);
+    }
+
+    #[test]
+    fn compares_with_the_last_capture_of_the_monitor() {
+        let mut detector = ChangeDetector::new(2);
+        assert_eq!(detector.unchanged_since(0, day(1), 0), None);
+
+        detector.saved(0, day(1), 0b1111, PathBuf::from("a"));
+        assert_eq!(
+            detector.unchanged_since(0, day(1), 0b1100),
+            Some(Path::new("a"))
+        );
+        assert_eq!(detector.unchanged_since(0, day(1), 0b1000), None);
+        assert_eq!(detector.unchanged_since(1, day(1), 0b1111), None);
+
+        detector.saved(0, day(1), 0, PathBuf::from("b"));
+        assert_eq!(detector.unchanged_since(0, day(1), 0), Some(Path::new("b")));
+    }
+
+    #[test]
+    fn never_compares_with_another_day() {
+        let mut detector = ChangeDetector::new(2);
+        detector.saved(0, day(1), 0, PathBuf::from("a"));
+        assert_eq!(detector.unchanged_since(0, day(2), 0), None);
+    }
+}
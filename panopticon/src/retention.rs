@@ -0,0 +1,325 @@
+use chrono::{Duration, NaiveDate};
+use panopticon_common::sidecar::Sidecar;
+use panopticon_common::store::Store;
+use panopticon_common::template::PathTemplate;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Limits on how much capture history is kept. Today is never pruned.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Days older than this many days are deleted
+    pub max_age_days: Option<u64>,
+    /// The oldest days are deleted until everything fits in this many bytes
+    pub max_total_bytes: Option<u64>,
+    /// Days without any labels are deleted once older than this many days
+    pub unlabeled_max_age_days: Option<u64>,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some()
+            || self.max_total_bytes.is_some()
+            || self.unlabeled_max_age_days.is_some()
+    }
+}
+
+#[derive(Default)]
+struct Day {
+    // left empty for a database, which deletes a day's rows at once
+    files: Vec<PathBuf>,
+    entries: u64,
+    bytes: u64,
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// removes the directories above a pruned file that it left empty, walking up to but not
+// including dir, so that empty directories the user made are left alone
+fn remove_empty_parents(dir: &Path, file: &Path) {
+    for parent in file.ancestors().skip(1) {
+        if parent == dir || !parent.starts_with(dir) {
+            break;
+        }
+        // fails once a directory still holds something
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+// the captures and sidecars of each day below dir
+fn files_by_day(
+    dir: &Path,
+    store: &Store,
+    template: &PathTemplate,
+) -> io::Result<BTreeMap<NaiveDate, Day>> {
+    let mut days: BTreeMap<NaiveDate, Day> = BTreeMap::new();
+    for capture in store.find_captures(template)? {
+        let path = dir.join(&capture.path);
+        let day = days.entry(capture.parsed.time.date()).or_default();
+        day.bytes += file_size(&path);
+        day.files.push(path);
+    }
+    // days whose captures are gone can still have sidecars
+    for date in store.days()? {
+        days.entry(date).or_default();
+    }
+    for (date, day) in days.iter_mut() {
+        for path in Sidecar::ALL.map(|s| s.path(dir, *date)) {
+            if path.exists() {
+                day.bytes += file_size(&path);
+                day.files.push(path);
+            }
+        }
+        day.entries = day.files.len() as u64;
+    }
+    Ok(days)
+}
+
+/// Deletes the captures and sidecars of every day that falls outside of `policy`,
+/// oldest first, logging each deletion.
+pub fn enforce(
+    store: &Store,
+    template: &PathTemplate,
+    policy: &RetentionPolicy,
+    today: NaiveDate,
+) -> io::Result<()> {
+    let days = match store {
+        Store::Files(dir) => files_by_day(dir, store, template)?,
+        Store::Sqlite(database) => database
+            .usage()?
+            .into_iter()
+            .map(|(date, (entries, bytes))| {
+                let day = Day {
+                    files: Vec::new(),
+                    entries,
+                    bytes,
+                };
+                (date, day)
+            })
+            .collect(),
+    };
+
+    let mut total_bytes: u64 = days.values().map(|d| d.bytes).sum();
+    let older_than = |max_age_days: Option<u64>, date: NaiveDate| {
+        max_age_days.is_some_and(|max| today - date > Duration::days(max as i64))
+    };
+
+    for (date, day) in days.range(..today) {
+        let reason = if older_than(policy.max_age_days, *date) {
+            format!(
+                "older than {} days",
+                policy.max_age_days.unwrap_or_default()
+            )
+        } else if policy.max_total_bytes.is_some_and(|max| total_bytes > max) {
+            format!(
+                "captures take {} bytes, more than the limit of {}",
+                total_bytes,
+                policy.max_total_bytes.unwrap_or_default()
+            )
+        } else if older_than(policy.unlabeled_max_age_days, *date)
+            && !store.contains(Sidecar::Labels, *date)?
+        {
+            format!(
+                "unlabeled and older than {} days",
+                policy.unlabeled_max_age_days.unwrap_or_default()
+            )
+        } else {
+            continue;
+        };
+
+        eprintln!(
+            "pruning {} ({} {}, {} bytes): {}",
+            date,
+            day.entries,
+            if day.files.is_empty() {
+                "rows"
+            } else {
+                "files"
+            },
+            day.bytes,
+            reason
+        );
+        match store {
+            Store::Files(dir) => {
+                for path in &day.files {
+                    match fs::remove_file(path) {
+                        Ok(()) => remove_empty_parents(dir, path),
+                        Err(e) => eprintln!("couldn't delete {}: {}", path.display(), e),
+                    }
+                }
+            }
+            Store::Sqlite(database) => database.remove_day(*date)?,
+        }
+        total_bytes -= day.bytes;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use chrono::{Local, TimeZone};
+    use panopticon_common::store::StoreKind;
+    use panopticon_common::template::{self, DEFAULT_TEMPLATE};
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 20).unwrap()
+    }
+
+    // saves a capture of 100 bytes at noon `days_ago` days before today
+    fn save_capture(store: &Store, template: &PathTemplate, days_ago: u64) {
+        let day = today() - Duration::days(days_ago as i64);
+        let time = Local
+            .from_local_datetime(&day.and_hms_opt(12, 0, 0).unwrap())
+            .earliest()
+            .unwrap();
+        let fields = template::Fields {
+            monitor_id: 0,
+            monitor_name: "monitor",
+            afk: false,
+            hostname: "host",
+        };
+        let mut path = template.render(&time, &fields).into_os_string();
+        path.push(".png");
+        store
+            .save_capture(Path::new(&path), day, &[0; 100])
+            .unwrap();
+    }
+
+    fn capture_days(store: &Store, template: &PathTemplate) -> Vec<u64> {
+        let mut days: Vec<u64> = store
+            .find_captures(template)
+            .unwrap()
+            .iter()
+            .map(|c| (today() - c.parsed.time.date()).num_days() as u64)
+            .collect();
+        days.sort();
+        days
+    }
+
+    #[test]
+    fn prunes_days_older_than_the_maximum_age() {
+        let dir = temp_dir("retention-age");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        for days_ago in [0, 3, 10] {
+            save_capture(&store, &template, days_ago);
+        }
+        let old_day = today() - Duration::days(10);
+        store.append_line(Sidecar::Log, old_day, "{}").unwrap();
+
+        let policy = RetentionPolicy {
+            max_age_days: Some(5),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert_eq!(capture_days(&store, &template), [0, 3]);
+        assert!(!Sidecar::Log.path(&dir, old_day).exists());
+        // the folder of the pruned day goes too
+        assert!(!dir.join(old_day.format("%Y-%m-%d").to_string()).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_empty_directories_it_didnt_empty() {
+        let dir = temp_dir("retention-empty-dirs");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        save_capture(&store, &template, 10);
+        let old_day = dir.join(
+            (today() - Duration::days(10))
+                .format("%Y-%m-%d")
+                .to_string(),
+        );
+        fs::create_dir_all(dir.join("exports/empty")).unwrap();
+
+        let policy = RetentionPolicy {
+            max_age_days: Some(5),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert!(!old_day.exists());
+        assert!(dir.join("exports/empty").is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_the_oldest_days_until_the_rest_fits() {
+        let dir = temp_dir("retention-size");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        for days_ago in [0, 1, 2] {
+            save_capture(&store, &template, days_ago);
+        }
+
+        let policy = RetentionPolicy {
+            max_total_bytes: Some(250),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert_eq!(capture_days(&store, &template), [0, 1]);
+
+        // today is kept even if it doesn't fit on its own
+        let policy = RetentionPolicy {
+            max_total_bytes: Some(50),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert_eq!(capture_days(&store, &template), [0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_labeled_days_longer() {
+        let dir = temp_dir("retention-labels");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Files, &template).unwrap();
+        for days_ago in [0, 9, 10, 11] {
+            save_capture(&store, &template, days_ago);
+        }
+        store
+            .append_line(Sidecar::Labels, today() - Duration::days(10), "{}")
+            .unwrap();
+        // emptied by the viewer, so it no longer counts as labeled
+        store
+            .append_line(Sidecar::Labels, today() - Duration::days(11), "")
+            .unwrap();
+
+        let policy = RetentionPolicy {
+            unlabeled_max_age_days: Some(5),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert_eq!(capture_days(&store, &template), [0, 10]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_rows_of_a_database() {
+        let dir = temp_dir("retention-sqlite");
+        let template = PathTemplate::new(DEFAULT_TEMPLATE).unwrap();
+        let store = Store::open(&dir, StoreKind::Sqlite, &template).unwrap();
+        for days_ago in [0, 3, 10] {
+            save_capture(&store, &template, days_ago);
+        }
+        let old_day = today() - Duration::days(10);
+        store.append_line(Sidecar::Log, old_day, "{}").unwrap();
+
+        let policy = RetentionPolicy {
+            max_age_days: Some(5),
+            ..Default::default()
+        };
+        enforce(&store, &template, &policy, today()).unwrap();
+        assert_eq!(capture_days(&store, &template), [0, 3]);
+        assert!(!store.contains(Sidecar::Log, old_day).unwrap());
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
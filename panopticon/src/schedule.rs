@@ -0,0 +1,205 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+use std::str::FromStr;
+
+/// A recurring window of time during which screenshots may be taken, written as eg.
+/// `Mon-Fri 09:00-17:30`, `Sat,Sun 10:00-12:00` or `* 22:00-02:00`.
+/// A window that ends before it starts runs past midnight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleWindow {
+    // indexed by Weekday::num_days_from_monday
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday, String> {
+    s.parse::<Weekday>()
+        .map_err(|_| format!("unknown day {:?}", s))
+}
+
+impl FromStr for ScheduleWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (days_str, times_str) = s
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| format!("expected \"<days> <HH:MM>-<HH:MM>\", got {:?}", s))?;
+
+        let mut days = [false; 7];
+        if days_str == "*" {
+            days = [true; 7];
+        } else {
+            for part in days_str.split(',') {
+                match part.split_once('-') {
+                    Some((first, last)) => {
+                        let mut day = parse_weekday(first)?;
+                        let last = parse_weekday(last)?;
+                        days[day.num_days_from_monday() as usize] = true;
+                        while day != last {
+                            day = day.succ();
+                            days[day.num_days_from_monday() as usize] = true;
+                        }
+                    }
+                    None => days[parse_weekday(part)?.num_days_from_monday() as usize] = true,
+                }
+            }
+        }
+
+        let (start, end) = times_str.trim().split_once('-').ok_or_else(|| {
+            format!(
+                "expected a time range like 09:00-17:00, got {:?}",
+                times_str
+            )
+        })?;
+        let parse_time =
+            |t: &str| NaiveTime::parse_from_str(t, "%H:%M").map_err(|e| format!("{:?}: {}", t, e));
+
+        Ok(ScheduleWindow {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl ScheduleWindow {
+    fn runs_on(&self, day: NaiveDate) -> bool {
+        self.days[day.weekday().num_days_from_monday() as usize]
+    }
+
+    fn wraps(&self) -> bool {
+        self.end <= self.start
+    }
+
+    /// The opening and closing of the window that starts on `day`
+    fn span_on(&self, day: NaiveDate) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        if !self.runs_on(day) {
+            return None;
+        }
+        let end_day = if self.wraps() { day.succ_opt()? } else { day };
+        // earliest() picks the first instant of an ambiguous time, and a time skipped
+        // by a DST change just means the window doesn't open that day
+        let start = Local
+            .from_local_datetime(&day.and_time(self.start))
+            .earliest()?;
+        let end = Local
+            .from_local_datetime(&end_day.and_time(self.end))
+            .earliest()?;
+        Some((start, end))
+    }
+}
+
+/// The union of the windows screenshots may be taken in. An empty schedule is always open.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    windows: Vec<ScheduleWindow>,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<ScheduleWindow>) -> Schedule {
+        Schedule { windows }
+    }
+
+    pub fn is_open(&self, time: DateTime<Local>) -> bool {
+        if self.windows.is_empty() {
+            return true;
+        }
+        let today = time.date_naive();
+        // a window that started yesterday may still be running
+        [today.pred_opt(), Some(today)]
+            .into_iter()
+            .flatten()
+            .flat_map(|day| self.windows.iter().filter_map(move |w| w.span_on(day)))
+            .any(|(start, end)| start <= time && time < end)
+    }
+
+    /// The next time after `time` at which a window opens, or `None` if none ever does
+    pub fn next_open(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let today = time.date_naive();
+        (0..=7)
+            .filter_map(|offset| today.checked_add_signed(Duration::days(offset)))
+            .flat_map(|day| self.windows.iter().filter_map(move |w| w.span_on(day)))
+            .map(|(start, _)| start)
+            .filter(|start| *start > time)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-15 was a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 1, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn schedule(windows: &[&str]) -> Schedule {
+        Schedule::new(windows.iter().map(|w| w.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window: ScheduleWindow = "Mon-Wed,Fri 09:00-17:30".parse().unwrap();
+        assert_eq!(window.days, [true, true, true, false, true, false, false]);
+        assert_eq!(window.start, NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        assert_eq!(window.end, NaiveTime::from_hms_opt(17, 30, 0).unwrap());
+        let window: ScheduleWindow = "* 22:00-02:00".parse().unwrap();
+        assert_eq!(window.days, [true; 7]);
+        // a range of days may run over the end of the week
+        let window: ScheduleWindow = "Sat-Mon 10:00-12:00".parse().unwrap();
+        assert_eq!(window.days, [true, false, false, false, false, true, true]);
+
+        assert!("Mon-Fri".parse::<ScheduleWindow>().is_err());
+        assert!("Funday 09:00-17:00".parse::<ScheduleWindow>().is_err());
+        assert!("Mon 09:00".parse::<ScheduleWindow>().is_err());
+        assert!("Mon 25:00-26:00".parse::<ScheduleWindow>().is_err());
+    }
+
+    #[test]
+    fn an_empty_schedule_is_always_open() {
+        let schedule = Schedule::default();
+        assert!(schedule.is_open(at(15, 3, 0)));
+        assert!(schedule.is_open(at(20, 23, 59)));
+    }
+
+    #[test]
+    fn open_from_start_to_end_on_its_days() {
+        let schedule = schedule(&["Mon-Fri 09:00-17:30"]);
+        assert!(!schedule.is_open(at(15, 8, 59)));
+        assert!(schedule.is_open(at(15, 9, 0)));
+        assert!(schedule.is_open(at(19, 17, 29)));
+        assert!(!schedule.is_open(at(19, 17, 30)));
+        assert!(!schedule.is_open(at(20, 12, 0)));
+    }
+
+    #[test]
+    fn windows_can_run_past_midnight() {
+        let schedule = schedule(&["Fri 22:00-02:00"]);
+        assert!(!schedule.is_open(at(19, 1, 0)));
+        assert!(schedule.is_open(at(19, 23, 0)));
+        assert!(schedule.is_open(at(20, 1, 59)));
+        assert!(!schedule.is_open(at(20, 2, 0)));
+        assert!(!schedule.is_open(at(20, 23, 0)));
+    }
+
+    #[test]
+    fn open_in_any_window() {
+        let schedule = schedule(&["Mon 09:00-12:00", "Mon 13:00-17:00"]);
+        assert!(schedule.is_open(at(15, 10, 0)));
+        assert!(!schedule.is_open(at(15, 12, 30)));
+        assert!(schedule.is_open(at(15, 14, 0)));
+    }
+
+    #[test]
+    fn finds_the_next_opening() {
+        let schedule = schedule(&["Mon-Fri 09:00-17:00"]);
+        assert_eq!(schedule.next_open(at(15, 8, 0)), Some(at(15, 9, 0)));
+        assert_eq!(schedule.next_open(at(15, 9, 0)), Some(at(16, 9, 0)));
+        assert_eq!(schedule.next_open(at(19, 18, 0)), Some(at(22, 9, 0)));
+        assert_eq!(Schedule::default().next_open(at(15, 8, 0)), None);
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process::Command;
+#[cfg(unix)]
+use std::{env, fs};
+
+// runs a service manager command, returning what it printed
+#[cfg(unix)]
+fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("couldn't run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "`{} {}` failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(unix)]
+fn home() -> Result<PathBuf, String> {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| "$HOME isn't set".to_string())
+}
+
+#[cfg(unix)]
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    fs::write(path, contents).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// the binary and directory the service runs from, so that relative paths in `args` keep
+// pointing where they did when it was installed
+#[cfg(unix)]
+fn invocation() -> Result<(PathBuf, PathBuf), String> {
+    let exe =
+        env::current_exe().map_err(|e| format!("couldn't find the panopticon binary: {}", e))?;
+    let cwd =
+        env::current_dir().map_err(|e| format!("couldn't find the current directory: {}", e))?;
+    Ok((exe, cwd))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod manager {
+    use super::*;
+
+    const UNIT: &str = "panopticon.service";
+
+    fn unit_path() -> Result<PathBuf, String> {
+        let config = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => home()?.join(".config"),
+        };
+        Ok(config.join("systemd").join("user").join(UNIT))
+    }
+
+    // quotes an argument of ExecStart, where systemd also expands % specifiers and $variables
+    fn quote(arg: &str) -> String {
+        let escaped = arg
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+            .replace('$', "$$");
+        format!("\"{}\"", escaped)
+    }
+
+    fn unit(exe: &Path, cwd: &Path, args: &[String]) -> String {
+        let exec_start = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args.iter().cloned())
+            .map(|arg| quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "[Unit]\n\
+             Description=Takes periodic screenshots\n\
+             PartOf=graphical-session.target\n\
+             After=graphical-session.target\n\
+             \n\
+             [Service]\n\
+             ExecStart={}\n\
+             WorkingDirectory={}\n\
+             Restart=on-failure\n\
+             RestartSec=10\n\
+             \n\
+             [Install]\n\
+             WantedBy=graphical-session.target\n",
+            exec_start,
+            cwd.to_string_lossy().replace('%', "%%"),
+        )
+    }
+
+    pub fn install(args: &[String]) -> Result<String, String> {
+        let (exe, cwd) = invocation()?;
+        let path = unit_path()?;
+        write_file(&path, &unit(&exe, &cwd, args))?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+        run("systemctl", &["--user", "enable", UNIT])?;
+        // restarting picks up the new options if an older install is running
+        run("systemctl", &["--user", "restart", UNIT])?;
+        Ok(format!("installed and started {}", path.display()))
+    }
+
+    pub fn uninstall() -> Result<String, String> {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Err(format!("no service installed at {}", path.display()));
+        }
+        run("systemctl", &["--user", "disable", "--now", UNIT])?;
+        fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+        Ok(format!("stopped and removed {}", path.display()))
+    }
+
+    pub fn status() -> Result<String, String> {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Ok("not installed".to_string());
+        }
+        // is-active exits with an error for anything but active, but still prints the state
+        let state = match run("systemctl", &["--user", "is-active", UNIT]) {
+            Ok(state) => state,
+            Err(_) => "not running".to_string(),
+        };
+        Ok(format!("installed at {}, {}", path.display(), state))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod manager {
+    use super::*;
+
+    const LABEL: &str = "com.github.panopticon";
+
+    fn plist_path() -> Result<PathBuf, String> {
+        Ok(home()?
+            .join("Library")
+            .join("LaunchAgents")
+            .join(format!("{}.plist", LABEL)))
+    }
+
+    fn xml_escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    fn plist(exe: &Path, cwd: &Path, args: &[String]) -> String {
+        let arguments: String = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args.iter().cloned())
+            .map(|arg| format!("        <string>{}</string>\n", xml_escape(&arg)))
+            .collect();
+        let log = env::temp_dir().join("panopticon.log");
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n\
+             \x20   <key>Label</key>\n\
+             \x20   <string>{}</string>\n\
+             \x20   <key>ProgramArguments</key>\n\
+             \x20   <array>\n\
+             {}\
+             \x20   </array>\n\
+             \x20   <key>WorkingDirectory</key>\n\
+             \x20   <string>{}</string>\n\
+             \x20   <key>RunAtLoad</key>\n\
+             \x20   <true/>\n\
+             \x20   <key>KeepAlive</key>\n\
+             \x20   <dict>\n\
+             \x20       <key>SuccessfulExit</key>\n\
+             \x20       <false/>\n\
+             \x20   </dict>\n\
+             \x20   <key>StandardErrorPath</key>\n\
+             \x20   <string>{}</string>\n\
+             </dict>\n\
+             </plist>\n",
+            LABEL,
+            arguments,
+            xml_escape(&cwd.to_string_lossy()),
+            xml_escape(&log.to_string_lossy()),
+        )
+    }
+
+    pub fn install(args: &[String]) -> Result<String, String> {
+        let (exe, cwd) = invocation()?;
+        let path = plist_path()?;
+        let path_str = path.to_string_lossy().into_owned();
+        // an older install has to be unloaded for the new options to be picked up
+        if path.exists() {
+            let _ = run("launchctl", &["unload", &path_str]);
+        }
+        write_file(&path, &plist(&exe, &cwd, args))?;
+        run("launchctl", &["load", "-w", &path_str])?;
+        Ok(format!("installed and started {}", path.display()))
+    }
+
+    pub fn uninstall() -> Result<String, String> {
+        let path = plist_path()?;
+        if !path.exists() {
+            return Err(format!("no service installed at {}", path.display()));
+        }
+        run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+        fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(format!("stopped and removed {}", path.display()))
+    }
+
+    pub fn status() -> Result<String, String> {
+        let path = plist_path()?;
+        if !path.exists() {
+            return Ok("not installed".to_string());
+        }
+        // launchctl list prints "PID Status Label" for every loaded agent, with "-" as
+        // the PID of one that isn't running
+        let running = run("launchctl", &["list"])?.lines().any(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            matches!(fields[..], [pid, _, label] if label == LABEL && pid != "-")
+        });
+        let state = if running { "running" } else { "not running" };
+        Ok(format!("installed at {}, {}", path.display(), state))
+    }
+}
+
+#[cfg(not(unix))]
+mod manager {
+    fn unsupported() -> Result<String, String> {
+        Err(
+            "installing panopticon as a service is only supported with systemd and launchd"
+                .to_string(),
+        )
+    }
+
+    pub fn install(_args: &[String]) -> Result<String, String> {
+        unsupported()
+    }
+
+    pub fn uninstall() -> Result<String, String> {
+        unsupported()
+    }
+
+    pub fn status() -> Result<String, String> {
+        unsupported()
+    }
+}
+
+pub use manager::{install, status, uninstall};
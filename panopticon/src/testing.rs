@@ -0,0 +1,10 @@
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+/// An empty directory for the test called `name`, left behind if the test fails
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("panopticon-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Duration, Local, TimeZone};
+use std::{thread, time};
+
+/// How long a sleep lasts at most before the wall clock is looked at again. Monotonic
+/// sleeps stand still while the system is suspended and ignore clock changes.
+const MAX_SLEEP_STEP: time::Duration = time::Duration::from_secs(1);
+
+/// A clock running back by less than this is assumed to be an NTP adjustment
+const CLOCK_JUMP_TOLERANCE: Duration = Duration::seconds(1);
+
+/// Ticks are due at fixed boundaries, multiples of the interval since the Unix epoch,
+/// so that the time spent capturing doesn't push the following ticks back
+#[derive(Clone, Copy, Debug)]
+pub struct Slots {
+    interval: Duration,
+}
+
+impl Slots {
+    pub fn new(interval_seconds: f32) -> Slots {
+        let millis = (f64::from(interval_seconds) * 1000.0).round() as i64;
+        Slots {
+            interval: Duration::milliseconds(millis.max(1)),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The first slot boundary at or after `time`
+    pub fn first_at_or_after(&self, time: DateTime<Local>) -> DateTime<Local> {
+        let interval = self.interval.num_milliseconds();
+        let millis = time.timestamp_millis();
+        let slots = millis.div_euclid(interval) + i64::from(millis.rem_euclid(interval) != 0);
+        Local
+            .timestamp_millis_opt(slots * interval)
+            .single()
+            .unwrap_or(time)
+    }
+
+    /// How many whole intervals `late` spans, ie. how many slots went by unused
+    pub fn missed(&self, late: Duration) -> i64 {
+        if late < self.interval {
+            0
+        } else {
+            late.num_milliseconds() / self.interval.num_milliseconds()
+        }
+    }
+}
+
+/// Sleeps until the wall clock reaches `target`, noticing suspends and clock changes.
+/// Returns false if it woke early since the clock was turned back.
+pub fn sleep_until(target: DateTime<Local>) -> bool {
+    let mut last_seen = Local::now();
+    while last_seen < target {
+        let remaining = (target - last_seen).to_std().unwrap_or_default();
+        thread::sleep(remaining.min(MAX_SLEEP_STEP));
+        let now = Local::now();
+        if now < last_seen - CLOCK_JUMP_TOLERANCE {
+            return false;
+        }
+        last_seen = now;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: i64) -> DateTime<Local> {
+        Local.timestamp_millis_opt(millis).unwrap()
+    }
+
+    #[test]
+    fn slots_fall_on_multiples_of_the_interval() {
+        let slots = Slots::new(5.0);
+        assert_eq!(slots.interval(), Duration::seconds(5));
+        assert_eq!(
+            slots.first_at_or_after(at(1_700_000_000_000)),
+            at(1_700_000_000_000)
+        );
+        assert_eq!(
+            slots.first_at_or_after(at(1_700_000_000_001)),
+            at(1_700_000_005_000)
+        );
+        assert_eq!(
+            slots.first_at_or_after(at(1_700_000_004_999)),
+            at(1_700_000_005_000)
+        );
+    }
+
+    #[test]
+    fn fractional_intervals_are_kept_to_the_millisecond() {
+        let slots = Slots::new(0.25);
+        assert_eq!(slots.interval(), Duration::milliseconds(250));
+        assert_eq!(slots.first_at_or_after(at(1_100)), at(1_250));
+        // too short an interval still moves on
+        assert_eq!(Slots::new(0.0).interval(), Duration::milliseconds(1));
+    }
+
+    #[test]
+    fn counts_whole_missed_slots() {
+        let slots = Slots::new(5.0);
+        assert_eq!(slots.missed(Duration::milliseconds(4_999)), 0);
+        assert_eq!(slots.missed(Duration::seconds(5)), 1);
+        assert_eq!(slots.missed(Duration::milliseconds(14_999)), 2);
+        assert_eq!(slots.missed(Duration::seconds(-3)), 0);
+    }
+}